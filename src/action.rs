@@ -0,0 +1,91 @@
+use anyhow::{anyhow, bail, Result};
+
+use govee_rs::models::{Color, Devices, PowerState};
+
+use crate::{capability, client::Client, scene::Scenes, settings::ActionSetting};
+
+/// A simple, config-defined operation on a set of devices.
+///
+/// These back the non-CLI entry points like hotkeys and http buttons.
+#[derive(Debug, Clone)]
+pub enum Action {
+    Toggle,
+    On,
+    Off,
+    Color(Color),
+    Brightness(u8),
+    Scene(String),
+}
+
+impl Action {
+    pub fn from_setting(setting: &ActionSetting) -> Result<Self> {
+        Ok(match setting.action.as_str() {
+            "toggle" => Self::Toggle,
+            "on" => Self::On,
+            "off" => Self::Off,
+            "color" => {
+                let color = setting
+                    .color
+                    .as_deref()
+                    .ok_or_else(|| anyhow!("The color action needs a color"))?;
+                Self::Color(Color::parse(color)?)
+            }
+            "brightness" => Self::Brightness(
+                setting
+                    .brightness
+                    .ok_or_else(|| anyhow!("The brightness action needs a brightness"))?,
+            ),
+            "scene" => Self::Scene(
+                setting
+                    .scene
+                    .clone()
+                    .ok_or_else(|| anyhow!("The scene action needs a scene"))?,
+            ),
+            other => bail!("Unknown action '{}'", other),
+        })
+    }
+
+    pub async fn run(&self, client: &Client, devices: &Devices) -> Result<()> {
+        if let Self::Scene(name) = self {
+            let path = Scenes::default_path()?;
+            let scenes = Scenes::load(&path)?;
+            let scene = scenes
+                .get(name)
+                .ok_or_else(|| anyhow!("No scene named '{}' in {}", name, path.display()))?;
+
+            for device in devices.iter() {
+                if let Some(snapshot) = scene.get(&device.name) {
+                    snapshot.apply(client, device).await?;
+                }
+            }
+
+            return Ok(());
+        }
+
+        for device in devices.iter() {
+            match self {
+                Self::Toggle => {
+                    let current = client.refresh(device).await?;
+                    let next = if current.power == Some(true) {
+                        PowerState::Off
+                    } else {
+                        PowerState::On
+                    };
+                    client.turn(device, next).await?;
+                }
+                Self::On => client.turn(device, PowerState::On).await?,
+                Self::Off => client.turn(device, PowerState::Off).await?,
+                // devices without color just turn on
+                Self::Color(_) if !capability::has_color(device) => {
+                    client.turn(device, PowerState::On).await?
+                }
+                Self::Color(color) => client.color(device, color.clone()).await?,
+                Self::Brightness(_) if !capability::is_light(device) => {}
+                Self::Brightness(value) => client.brightness(device, *value).await?,
+                Self::Scene(_) => unreachable!("scenes are applied above"),
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::state::DeviceSnapshot;
+
+/// Devices currently held in a sticky alert state.
+///
+/// Each entry remembers what the device looked like before the alert so that
+/// acknowledging it can put things back.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Alerts {
+    #[serde(default)]
+    pub devices: BTreeMap<String, DeviceSnapshot>,
+}
+
+impl Alerts {
+    /// The alerts file, `spirit/alerts.toml` in the local data directory.
+    pub fn path() -> Result<PathBuf> {
+        match dirs::data_local_dir() {
+            Some(dir) => Ok(dir.join("spirit").join("alerts.toml")),
+            None => bail!("Could not determine local data directory for alerts file"),
+        }
+    }
+
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let raw = fs::read_to_string(&path)
+            .with_context(|| format!("Could not read alerts file {}", path.display()))?;
+
+        toml::from_str(&raw)
+            .with_context(|| format!("Could not parse alerts file {}", path.display()))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&path, toml::to_string_pretty(self)?)
+            .with_context(|| format!("Could not write alerts file {}", path.display()))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.devices.contains_key(name)
+    }
+
+    /// Mark `name` as alerted, keeping the earliest pre-alert snapshot if the
+    /// device was already alerted.
+    pub fn raise(&mut self, name: &str, previous: DeviceSnapshot) {
+        self.devices.entry(name.to_string()).or_insert(previous);
+    }
+
+    pub fn clear(&mut self, name: &str) -> Option<DeviceSnapshot> {
+        self.devices.remove(name)
+    }
+}
@@ -0,0 +1,202 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{NaiveTime, Timelike};
+use std::time::Duration;
+
+use govee_rs::models::Device;
+
+use crate::settings::{AutoOffSetting, DeviceSetting};
+
+/// A guard against lights left on: devices on for longer than `after`
+/// (within the optional daily window) are turned off or dimmed.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    devices: Vec<String>,
+    groups: Vec<String>,
+    pub after: Duration,
+    /// (start, end) in seconds from midnight; may wrap past midnight.
+    window: Option<(u32, u32)>,
+    pub dim: Option<u8>,
+}
+
+impl Rule {
+    pub fn from_setting(setting: &AutoOffSetting) -> Result<Self> {
+        let after = humantime::parse_duration(&setting.after)
+            .with_context(|| format!("Invalid auto_off duration '{}'", setting.after))?;
+
+        let window = match setting.between {
+            Some(ref between) => {
+                let (start, end) = between.split_once('-').ok_or_else(|| {
+                    anyhow!(
+                        "Invalid auto_off window '{}' (expected HH:MM-HH:MM)",
+                        between
+                    )
+                })?;
+                Some((parse_time(start)?, parse_time(end)?))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            devices: setting.devices.clone().unwrap_or_default(),
+            groups: setting.groups.clone().unwrap_or_default(),
+            after,
+            window,
+            dim: setting.dim,
+        })
+    }
+
+    /// Whether the rule covers `device`. A rule without devices or groups
+    /// covers every watched device.
+    pub fn applies_to(&self, device: &Device, setting: Option<&DeviceSetting>) -> bool {
+        if self.devices.is_empty() && self.groups.is_empty() {
+            return true;
+        }
+
+        self.devices.contains(&device.name)
+            || setting
+                .map(|s| s.in_any_group(&self.groups))
+                .unwrap_or(false)
+    }
+
+    pub fn groups(&self) -> &[String] {
+        &self.groups
+    }
+
+    pub fn devices(&self) -> &[String] {
+        &self.devices
+    }
+
+    /// A window that starts and ends at the same time contains no time at
+    /// all.
+    pub fn never_fires(&self) -> bool {
+        matches!(self.window, Some((start, end)) if start == end)
+    }
+
+    /// Whether there's a time of day both rules are active.
+    pub fn overlaps(&self, other: &Self) -> bool {
+        let a = ranges(self.window);
+        let b = ranges(other.window);
+
+        a.iter()
+            .any(|(s1, e1)| b.iter().any(|(s2, e2)| s1 < e2 && s2 < e1))
+    }
+
+    pub fn in_window(&self, time: NaiveTime) -> bool {
+        let now = time.num_seconds_from_midnight();
+
+        match self.window {
+            None => true,
+            Some((start, end)) if start <= end => start <= now && now < end,
+            Some((start, end)) => now >= start || now < end,
+        }
+    }
+}
+
+/// The window as non-wrapping (start, end) ranges within a day.
+fn ranges(window: Option<(u32, u32)>) -> Vec<(u32, u32)> {
+    const DAY: u32 = 24 * 3600;
+
+    match window {
+        None => vec![(0, DAY)],
+        Some((start, end)) if start <= end => vec![(start, end)],
+        Some((start, end)) => vec![(start, DAY), (0, end)],
+    }
+}
+
+fn parse_time(raw: &str) -> Result<u32> {
+    NaiveTime::parse_from_str(raw.trim(), "%H:%M")
+        .map(|t| t.num_seconds_from_midnight())
+        .map_err(|e| anyhow!("Invalid auto_off time '{}' (expected HH:MM): {}", raw, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(between: Option<&str>) -> Result<Rule> {
+        Rule::from_setting(&AutoOffSetting {
+            devices: None,
+            groups: None,
+            after: "1h".to_string(),
+            between: between.map(String::from),
+            dim: None,
+        })
+    }
+
+    fn at(h: u32, m: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(h, m, 0).unwrap()
+    }
+
+    #[test]
+    fn windows_within_a_day() {
+        let rule = rule(Some("09:00-17:00")).unwrap();
+
+        assert!(!rule.in_window(at(8, 59)));
+        assert!(rule.in_window(at(9, 0)));
+        assert!(rule.in_window(at(16, 59)));
+        assert!(!rule.in_window(at(17, 0)));
+    }
+
+    #[test]
+    fn windows_crossing_midnight() {
+        let rule = rule(Some("23:00-06:00")).unwrap();
+
+        assert!(rule.in_window(at(23, 0)));
+        assert!(rule.in_window(at(0, 0)));
+        assert!(rule.in_window(at(5, 59)));
+        assert!(!rule.in_window(at(6, 0)));
+        assert!(!rule.in_window(at(12, 0)));
+        assert!(!rule.in_window(at(22, 59)));
+    }
+
+    #[test]
+    fn no_window_is_always_active() {
+        let rule = rule(None).unwrap();
+
+        assert!(rule.in_window(at(0, 0)));
+        assert!(rule.in_window(at(23, 59)));
+        assert!(!rule.never_fires());
+    }
+
+    #[test]
+    fn empty_windows_never_fire() {
+        let rule = rule(Some("10:00-10:00")).unwrap();
+
+        assert!(rule.never_fires());
+        assert!(!rule.in_window(at(10, 0)));
+    }
+
+    #[test]
+    fn overlaps() {
+        let day = rule(Some("09:00-17:00")).unwrap();
+        let evening = rule(Some("17:00-23:00")).unwrap();
+        let night = rule(Some("22:00-07:00")).unwrap();
+        let early = rule(Some("06:00-08:00")).unwrap();
+        let always = rule(None).unwrap();
+
+        // touching windows don't overlap
+        assert!(!day.overlaps(&evening));
+        assert!(evening.overlaps(&night));
+        assert!(night.overlaps(&early));
+        assert!(early.overlaps(&night));
+        assert!(!day.overlaps(&night));
+        assert!(always.overlaps(&day));
+        assert!(always.overlaps(&night));
+    }
+
+    #[test]
+    fn rejects_malformed_settings() {
+        assert!(rule(Some("09:00")).is_err());
+        assert!(rule(Some("9am-5pm")).is_err());
+        assert!(rule(Some("25:00-06:00")).is_err());
+
+        assert!(Rule::from_setting(&AutoOffSetting {
+            devices: None,
+            groups: None,
+            after: "a while".to_string(),
+            between: None,
+            dim: None,
+        })
+        .is_err());
+    }
+}
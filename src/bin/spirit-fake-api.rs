@@ -0,0 +1,299 @@
+//! A stand-in for the Govee cloud api, for exercising spirit offline.
+//!
+//! Run it with `cargo run --features fake-api --bin spirit-fake-api` and
+//! point spirit at it with `--api-url`. The first line printed is the
+//! address it is listening on, which is handy when binding to port 0.
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use clap::Parser;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+};
+
+/// Emulate the Govee device endpoints with a set of fake lights.
+#[derive(Parser)]
+#[command(author, version)]
+struct Args {
+    /// The address to listen on.
+    #[arg(long, default_value = "127.0.0.1:8081")]
+    addr: SocketAddr,
+
+    /// How many fake devices to serve.
+    #[arg(long, default_value_t = 3)]
+    devices: usize,
+
+    /// Delay every response by this much.
+    #[arg(long, default_value = "0s", value_parser = humantime::parse_duration)]
+    latency: Duration,
+
+    /// Answer 429 once more than this many requests arrive in a minute.
+    #[arg(long)]
+    rate_limit: Option<usize>,
+}
+
+struct FakeDevice {
+    id: String,
+    model: String,
+    name: String,
+    on: bool,
+    brightness: u64,
+    color: (u64, u64, u64),
+}
+
+struct State {
+    devices: Vec<FakeDevice>,
+    recent: VecDeque<Instant>,
+    rate_limit: Option<usize>,
+}
+
+impl State {
+    fn new(count: usize, rate_limit: Option<usize>) -> Self {
+        let devices = (1..=count)
+            .map(|i| FakeDevice {
+                id: format!("AA:BB:CC:DD:EE:FF:00:{:02X}", i),
+                model: "H6159".into(),
+                name: format!("Fake Light {}", i),
+                on: false,
+                brightness: 100,
+                color: (255, 255, 255),
+            })
+            .collect();
+
+        Self {
+            devices,
+            recent: VecDeque::new(),
+            rate_limit,
+        }
+    }
+
+    /// Whether this request goes over the per-minute limit.
+    fn limited(&mut self) -> bool {
+        let now = Instant::now();
+        while let Some(at) = self.recent.front() {
+            if now.duration_since(*at) > Duration::from_secs(60) {
+                self.recent.pop_front();
+            } else {
+                break;
+            }
+        }
+
+        match self.rate_limit {
+            Some(limit) if self.recent.len() >= limit => true,
+            _ => {
+                self.recent.push_back(now);
+                false
+            }
+        }
+    }
+
+    fn respond(&mut self, method: &str, target: &str, body: &str) -> (&'static str, Value) {
+        if self.limited() {
+            return ("429 Too Many Requests", message(429, "Too Many Requests"));
+        }
+
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+        match (method, path) {
+            ("GET", "/v1/devices") => ("200 OK", self.list()),
+            ("GET", "/v1/devices/state") => {
+                let id = query.split('&').find_map(|p| p.strip_prefix("device="));
+                match id.map(decode).and_then(|id| self.find(&id)) {
+                    Some(device) => ("200 OK", state(device)),
+                    None => ("400 Bad Request", message(400, "device not found")),
+                }
+            }
+            ("PUT", "/v1/devices/control") => match self.control(body) {
+                Ok(()) => ("200 OK", message(200, "Success")),
+                Err(e) => ("400 Bad Request", message(400, &e.to_string())),
+            },
+            _ => ("404 Not Found", message(404, "not found")),
+        }
+    }
+
+    fn find(&self, id: &str) -> Option<&FakeDevice> {
+        self.devices.iter().find(|d| d.id == id)
+    }
+
+    fn list(&self) -> Value {
+        let devices: Vec<Value> = self
+            .devices
+            .iter()
+            .map(|d| {
+                json!({
+                    "device": d.id,
+                    "model": d.model,
+                    "deviceName": d.name,
+                    "controllable": true,
+                    "retrievable": true,
+                    "supportCmds": ["turn", "brightness", "color", "colorTem"],
+                    "properties": {
+                        "colorTem": { "range": { "min": 2000, "max": 9000 } }
+                    }
+                })
+            })
+            .collect();
+
+        json!({
+            "code": 200,
+            "message": "Success",
+            "data": { "devices": devices }
+        })
+    }
+
+    fn control(&mut self, body: &str) -> Result<()> {
+        let request: Value = serde_json::from_str(body).context("invalid json")?;
+        let id = request["device"].as_str().unwrap_or_default();
+        let cmd = &request["cmd"];
+
+        let device = match self.devices.iter_mut().find(|d| d.id == id) {
+            Some(device) => device,
+            None => bail!("device not found"),
+        };
+
+        match cmd["name"].as_str() {
+            Some("turn") => device.on = cmd["value"].as_str() == Some("on"),
+            Some("brightness") => {
+                device.brightness = cmd["value"].as_u64().context("invalid brightness")?;
+                device.on = true;
+            }
+            Some("color") => {
+                let value = &cmd["value"];
+                device.color = (
+                    value["r"].as_u64().context("invalid color")?,
+                    value["g"].as_u64().context("invalid color")?,
+                    value["b"].as_u64().context("invalid color")?,
+                );
+                device.on = true;
+            }
+            Some("colorTem") => device.on = true,
+            _ => bail!("unsupported command"),
+        }
+
+        Ok(())
+    }
+}
+
+fn state(device: &FakeDevice) -> Value {
+    json!({
+        "code": 200,
+        "message": "Success",
+        "data": {
+            "device": device.id,
+            "model": device.model,
+            "properties": [
+                { "online": true },
+                { "powerState": if device.on { "on" } else { "off" } },
+                { "brightness": device.brightness },
+                { "color": { "r": device.color.0, "g": device.color.1, "b": device.color.2 } }
+            ]
+        }
+    })
+}
+
+fn message(code: u16, message: &str) -> Value {
+    json!({ "code": code, "message": message, "data": {} })
+}
+
+/// Undo the percent-encoding clients apply to device ids in query strings.
+fn decode(raw: &str) -> String {
+    let mut out = Vec::with_capacity(raw.len());
+    let bytes = raw.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                out.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+async fn handle(mut stream: TcpStream, state: Arc<Mutex<State>>, latency: Duration) -> Result<()> {
+    let mut reader = BufReader::new(&mut stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default().to_string();
+
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().context("invalid content length")?;
+            }
+        }
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).await?;
+    let body = String::from_utf8_lossy(&body);
+
+    tokio::time::sleep(latency).await;
+
+    let (status, response) = state
+        .lock()
+        .expect("state lock poisoned")
+        .respond(&method, &target, &body);
+    let response = response.to_string();
+
+    let raw = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        response.len(),
+        response
+    );
+
+    stream.write_all(raw.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let listener = TcpListener::bind(args.addr)
+        .await
+        .with_context(|| format!("Could not listen on {}", args.addr))?;
+
+    println!("http://{}", listener.local_addr()?);
+
+    let state = Arc::new(Mutex::new(State::new(args.devices, args.rate_limit)));
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let state = state.clone();
+        let latency = args.latency;
+
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, state, latency).await {
+                eprintln!("Request failed: {:#}", e);
+            }
+        });
+    }
+}
@@ -0,0 +1,180 @@
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use govee_rs::models::Devices;
+use serde::{Deserialize, Serialize};
+
+use crate::{client::Client, state::DeviceSnapshot};
+
+/// Device snapshots persisted between invocations so frequently polled
+/// commands don't have to hit the API every time.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct StateCache {
+    #[serde(default)]
+    pub devices: BTreeMap<String, CachedSnapshot>,
+    /// The cloud api's device list, for commands polled often enough that
+    /// fetching it every time would add up.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_list: Option<CachedDevices>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedDevices {
+    /// Seconds since the unix epoch at which the list was fetched.
+    pub fetched: u64,
+    pub devices: Devices,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedSnapshot {
+    /// Seconds since the unix epoch at which this snapshot was taken.
+    pub fetched: u64,
+    /// Seconds since the unix epoch at which the state was last seen to
+    /// differ from the snapshot before it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changed: Option<u64>,
+    pub snapshot: DeviceSnapshot,
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl StateCache {
+    /// The cache file, `spirit/states.toml` in the cache directory.
+    pub fn path() -> Result<PathBuf> {
+        match dirs::cache_dir() {
+            Some(dir) => Ok(dir.join("spirit").join("states.toml")),
+            None => bail!("Could not determine cache directory for state cache"),
+        }
+    }
+
+    /// Load the cache, treating an unreadable cache as empty.
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let raw = fs::read_to_string(&path)
+            .with_context(|| format!("Could not read state cache {}", path.display()))?;
+
+        Ok(toml::from_str(&raw).unwrap_or_default())
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&path, toml::to_string_pretty(self)?)
+            .with_context(|| format!("Could not write state cache {}", path.display()))
+    }
+
+    /// Store a freshly fetched snapshot, returning whether the device's state
+    /// changed since the last one.
+    ///
+    /// Only power, brightness and color count as state; connectivity isn't
+    /// persisted.
+    pub fn record(&mut self, name: &str, snapshot: DeviceSnapshot, now: u64) -> bool {
+        let previous = self.devices.get(name);
+
+        let changed = match previous {
+            Some(previous) => {
+                let old = &previous.snapshot;
+                old.power != snapshot.power
+                    || old.brightness != snapshot.brightness
+                    || old.color != snapshot.color
+            }
+            None => false,
+        };
+
+        let changed_at = if changed {
+            Some(now)
+        } else {
+            previous.and_then(|p| p.changed)
+        };
+
+        self.devices.insert(
+            name.to_string(),
+            CachedSnapshot {
+                fetched: now,
+                changed: changed_at,
+                snapshot,
+            },
+        );
+
+        changed
+    }
+
+    /// Get the cloud device list, fetching it again if the cached one is
+    /// older than `ttl`.
+    ///
+    /// The cache is saved if the list was fetched.
+    pub async fn device_list(&mut self, client: &Client, ttl: Duration) -> Result<Devices> {
+        let now = now();
+
+        if let Some(ref cached) = self.device_list {
+            if now.saturating_sub(cached.fetched) < ttl.as_secs() {
+                return Ok(cached.devices.clone());
+            }
+        }
+
+        let devices = client.devices().await?;
+        self.device_list = Some(CachedDevices {
+            fetched: now,
+            devices: devices.clone(),
+        });
+        self.save()?;
+
+        Ok(devices)
+    }
+
+    /// Get snapshots for `devices`, refreshing any entries older than `ttl`.
+    ///
+    /// The cache is saved if anything was refreshed.
+    pub async fn refresh(
+        &mut self,
+        client: &Client,
+        devices: &Devices,
+        ttl: Duration,
+    ) -> Result<Vec<DeviceSnapshot>> {
+        let now = now();
+        let mut dirty = false;
+        let mut out = Vec::with_capacity(devices.devices.len());
+
+        for device in devices.iter() {
+            let fresh = self
+                .devices
+                .get(&device.name)
+                .filter(|c| now.saturating_sub(c.fetched) < ttl.as_secs());
+
+            let snapshot = match fresh {
+                Some(cached) => cached.snapshot.clone(),
+                None => {
+                    let snapshot = client.refresh(device).await?;
+                    self.record(&device.name, snapshot.clone(), now);
+                    dirty = true;
+                    snapshot
+                }
+            };
+
+            out.push(snapshot);
+        }
+
+        if dirty {
+            self.save()?;
+        }
+
+        Ok(out)
+    }
+}
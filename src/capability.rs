@@ -0,0 +1,63 @@
+use govee_rs::models::{Device, Devices};
+
+/// What kind of thing a device is, judged by the commands it accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Accepts color or brightness.
+    Light,
+    /// Only turns on and off, like smart plugs and switches.
+    Switch,
+}
+
+impl Kind {
+    pub fn of(device: &Device) -> Self {
+        if supports(device, "color") || supports(device, "brightness") {
+            Self::Light
+        } else {
+            Self::Switch
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Light => "light",
+            Self::Switch => "switch",
+        }
+    }
+}
+
+pub fn supports(device: &Device, cmd: &str) -> bool {
+    device.support_cmds.iter().any(|c| c == cmd)
+}
+
+pub fn is_light(device: &Device) -> bool {
+    Kind::of(device) == Kind::Light
+}
+
+/// Whether `device` can show a color. Some lights only dim.
+pub fn has_color(device: &Device) -> bool {
+    supports(device, "color")
+}
+
+/// Whether `device` can be set to a white by color temperature.
+pub fn has_color_temperature(device: &Device) -> bool {
+    supports(device, "colorTem")
+}
+
+/// Only the devices in `devices` that can show a color, for commands that
+/// are all about color.
+pub fn color_lights(devices: &Devices) -> Devices {
+    let mut lights = devices.clone();
+    lights.devices.retain(has_color);
+    lights
+}
+
+/// Only the devices in `devices` that can show a color or a color
+/// temperature, for commands that set whites.
+pub fn white_lights(devices: &Devices) -> Devices {
+    let mut lights = devices.clone();
+    lights
+        .devices
+        .retain(|d| has_color(d) || has_color_temperature(d));
+    lights
+}
@@ -0,0 +1,129 @@
+use std::{env, fmt, io};
+
+use is_terminal::IsTerminal;
+
+/// A CI system spirit knows how to recognize.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    GithubActions,
+    Gitlab,
+    Azure,
+    Circle,
+    Buildkite,
+    Jenkins,
+    /// Something that sets `CI` but isn't otherwise recognized.
+    Generic,
+}
+
+impl fmt::Display for Provider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::GithubActions => "github-actions",
+            Self::Gitlab => "gitlab",
+            Self::Azure => "azure-pipelines",
+            Self::Circle => "circleci",
+            Self::Buildkite => "buildkite",
+            Self::Jenkins => "jenkins",
+            Self::Generic => "generic",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Notice,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Notice => "notice",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What spirit can tell about where it's running from the environment.
+#[derive(Debug, Clone, Copy)]
+pub struct Environment {
+    pub provider: Option<Provider>,
+    /// `SPIRIT_DISABLE=1`, which skips every device operation.
+    pub disabled: bool,
+    /// Whether stdin is a terminal someone could answer a prompt on, rather
+    /// than a pipe, a file or nothing (as under cron).
+    pub terminal: bool,
+}
+
+impl Environment {
+    pub fn detect() -> Self {
+        let set = |name: &str| env::var_os(name).map(|v| !v.is_empty()).unwrap_or(false);
+        let truthy = |name: &str| {
+            env::var(name)
+                .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "yes"))
+                .unwrap_or(false)
+        };
+
+        let provider = if truthy("GITHUB_ACTIONS") {
+            Some(Provider::GithubActions)
+        } else if set("GITLAB_CI") {
+            Some(Provider::Gitlab)
+        } else if set("TF_BUILD") {
+            Some(Provider::Azure)
+        } else if set("CIRCLECI") {
+            Some(Provider::Circle)
+        } else if set("BUILDKITE") {
+            Some(Provider::Buildkite)
+        } else if set("JENKINS_URL") {
+            Some(Provider::Jenkins)
+        } else if truthy("CI") {
+            Some(Provider::Generic)
+        } else {
+            None
+        };
+
+        Self {
+            provider,
+            disabled: truthy("SPIRIT_DISABLE"),
+            terminal: io::stdin().is_terminal(),
+        }
+    }
+
+    pub fn is_ci(&self) -> bool {
+        self.provider.is_some()
+    }
+
+    /// Whether it's reasonable to stop and ask a question.
+    pub fn interactive(&self) -> bool {
+        !self.is_ci() && self.terminal
+    }
+
+    /// Report something in the form the CI system picks up as an annotation,
+    /// or as a plain `level: message` line on stderr everywhere else.
+    pub fn annotate(&self, level: Level, message: impl fmt::Display) {
+        let message = message.to_string();
+
+        match self.provider {
+            Some(Provider::GithubActions) => {
+                let escaped = message
+                    .replace('%', "%25")
+                    .replace('\r', "%0D")
+                    .replace('\n', "%0A");
+                println!("::{}::{}", level, escaped);
+            }
+            Some(Provider::Azure) => {
+                // azure only has warnings and errors
+                let kind = match level {
+                    Level::Error => "error",
+                    _ => "warning",
+                };
+                println!("##vso[task.logissue type={}]{}", kind, message);
+            }
+            _ => eprintln!("{}: {}", level, message),
+        }
+    }
+}
@@ -1,32 +1,113 @@
-use std::{collections::HashSet, process::Command};
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    fs,
+    io::{self, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Duration,
+};
 
 use anyhow::{anyhow, bail, Context, Result};
-use clap::{Args, Parser, Subcommand};
+use chrono::{Local, TimeZone};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use futures::{stream, StreamExt};
 use govee_rs::{
-    models::{Devices, PowerState},
+    models::{Color, Device, Devices, PowerState},
     GoveeClient, DEFAULT_API_URL,
 };
+use rand::{seq::SliceRandom, Rng};
+use serde::Serialize;
+
+use crate::{
+    alerts::Alerts,
+    cache::{self, StateCache},
+    capability::{self, Kind},
+    ci::{self, Level},
+    client::Client,
+    color::{Deficiency, Rgb},
+    events,
+    flux::Curve,
+    focus::Attention,
+    health::Health,
+    history::{self, History},
+    identify,
+    lan::LanClient,
+    lint::{self, Severity},
+    names::ColorNames,
+    nextest::Summary,
+    notify::{Event, Notification, Notifiers},
+    palette::Palette,
+    scene::{self, Scene, Scenes},
+    sequence::Sequence,
+    service::Service,
+    session::Session,
+    settings::{CheckSetting, DeviceSettingMap, KeylightSetting, Settings},
+    signal::{self, Pattern},
+    state::DeviceSnapshot,
+    template::{DeviceContext, Template},
+    webcam,
+};
 
-use crate::settings::Settings;
+/// How long a command gets to stop after ctrl-c before it's dropped.
+const CANCEL_GRACE: Duration = Duration::from_secs(2);
 
 /// A command-line interface for controlling sets of govee lights.
 #[derive(Parser)]
 #[command(author, version)]
 pub struct Cli {
     /// The govee api key.
+    ///
+    /// Required everywhere but CI, where leaving it unset skips the lights.
     #[arg(short, long, env = "GOVEE_KEY", hide_env_values = true)]
-    govee_key: String,
+    govee_key: Option<String>,
+
+    /// The base url of the govee api.
+    #[arg(long, env = "GOVEE_API_URL", default_value = DEFAULT_API_URL)]
+    api_url: String,
 
     /// Operate on all devices regardless of config.
     #[arg(short, long)]
     all: bool,
 
-    /// The device name. May be specified multiple times.
+    /// The device name or alias. May be specified multiple times.
     ///
-    /// If not provided will operate on all devices specified by the config.
+    /// If neither this nor --group is provided will operate on the current
+    /// `spirit session`, then the devices and groups in SPIRIT_DEVICES and
+    /// SPIRIT_GROUP (comma separated), or failing that all devices specified
+    /// by the config.
     #[arg(short, long, conflicts_with = "all")]
     device: Vec<String>,
 
+    /// Operate on the configured devices in this group. May be specified
+    /// multiple times.
+    #[arg(short = 'G', long, conflicts_with = "all")]
+    group: Vec<String>,
+
+    /// Cancel device operations still running this long after the first one
+    /// starts (e.g. 30s, 2m).
+    ///
+    /// A summary of which devices were completed and which were skipped is
+    /// printed if this cuts anything short.
+    #[arg(long, global = true, value_parser = humantime::parse_duration)]
+    max_duration: Option<Duration>,
+
+    /// Skip the confirmation the `safety` config asks for on wide selections.
+    ///
+    /// There's never a confirmation in CI or when stdin isn't a terminal.
+    #[arg(short, long, global = true)]
+    yes: bool,
+
+    /// Don't print the per-device result table after commands that change
+    /// several devices.
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Apply the overrides from this entry in the config's `profiles`.
+    #[arg(long, global = true, env = "SPIRIT_PROFILE")]
+    profile: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -34,29 +115,253 @@ pub struct Cli {
 impl Cli {
     pub async fn run() -> Result<()> {
         let cli = Self::parse();
+        let ci = ci::Environment::detect();
 
-        let settings = Settings::new()
-            .context("Could not load spirit.toml file")?
-            .ok_or_else(|| anyhow!("spirit.toml evaluated to an empty settings object"))?;
+        // these only read local files, so don't require config or the api
+        match cli.command {
+            Commands::Events(ref cmd) => return cmd.run().await,
+            Commands::Health(ref cmd) => return cmd.run(),
+            Commands::Service(ref cmd) => return cmd.run(cli.govee_key()),
+            Commands::CiEnv(ref cmd) => return cmd.run(&ci, cli.govee_key.is_some()),
+            Commands::Session(ref cmd) => return cmd.run(cli.profile.as_deref(), cli.quiet),
+            _ => {}
+        }
 
-        let client = GoveeClient::new(DEFAULT_API_URL, &cli.govee_key)?;
+        // flags beat the session for the profile and quiet mode too
+        let session = Session::load()?;
+        let profile = cli
+            .profile
+            .as_deref()
+            .or_else(|| session.as_ref().and_then(|s| s.profile.as_deref()));
+        let quiet = cli.quiet || session.as_ref().map(|s| s.quiet).unwrap_or(false);
 
-        cli.command
-            .run(
-                &client,
-                &settings,
-                &cli.get_devices(&client, &settings).await?,
-            )
-            .await
+        let mut settings = Settings::new().context("Could not load spirit.toml file")?;
+        if let Some(profile) = profile {
+            settings.use_profile(profile)?;
+        }
+
+        // only needs config
+        match cli.command {
+            Commands::Palette(ref cmd) => return cmd.run(&settings),
+            Commands::History(ref cmd) => return cmd.run(&settings),
+            _ => {}
+        }
+
+        if ci.disabled {
+            return cli.skip_devices(&ci, &settings, "SPIRIT_DISABLE is set");
+        }
+
+        if cli.govee_key.is_none() && ci.is_ci() {
+            return cli.skip_devices(&ci, &settings, "no GOVEE_KEY in this environment");
+        }
+
+        let client = Client::new(GoveeClient::new(&cli.api_url, cli.govee_key()?)?, &settings)
+            .with_max_duration(cli.max_duration)
+            .with_quiet(quiet);
+
+        let devices = if cli.command.uses_selection() {
+            cli.get_devices(&client, &settings, session.as_ref()).await
+        } else {
+            cli.list_devices(&client).await
+        };
+
+        let mut devices = match devices {
+            Ok(devices) => devices,
+            Err(e) if ci.is_ci() => {
+                return cli.skip_devices(
+                    &ci,
+                    &settings,
+                    format!("could not reach devices: {:#}", e),
+                );
+            }
+            Err(e) => return Err(e),
+        };
+
+        if cli.command.needs_state() {
+            client.prefetch(&devices).await;
+        }
+
+        if cli.command.respects_alerts() {
+            cli.hold_alerted(&client, &mut devices).await?;
+        }
+
+        if cli.command.changes_state() && !cli.yes && ci.interactive() {
+            cli.confirm(&settings, &devices)?;
+        }
+
+        // the first ctrl-c stops device operations and gives the command a
+        // moment to wind down, a second quits outright
+        let run = cli.command.run(&client, &settings, &devices);
+        tokio::pin!(run);
+
+        let res = tokio::select! {
+            res = &mut run => res,
+            _ = tokio::signal::ctrl_c() => {
+                client.cancel();
+                tokio::select! {
+                    res = tokio::time::timeout(CANCEL_GRACE, &mut run) => {
+                        res.unwrap_or_else(|_| Err(anyhow!("Interrupted")))
+                    }
+                    _ = tokio::signal::ctrl_c() => Err(anyhow!("Interrupted")),
+                }
+            }
+        };
+
+        if let Some(table) = client.result_summary(&devices) {
+            eprintln!("{}", table);
+        }
+
+        if let Some(summary) = client.stop_summary(&devices) {
+            eprintln!("{}", summary);
+        }
+
+        res
+    }
+
+    fn govee_key(&self) -> Result<&str> {
+        self.govee_key
+            .as_deref()
+            .ok_or_else(|| anyhow!("No govee api key, pass --govee-key or set GOVEE_KEY"))
+    }
+
+    /// Carry on without touching any devices.
+    ///
+    /// A check still runs its command and exits with its code, so the same
+    /// `spirit check` line works where there are no lights to show it on.
+    fn skip_devices(
+        &self,
+        ci: &ci::Environment,
+        settings: &Settings,
+        reason: impl Display,
+    ) -> Result<()> {
+        ci.annotate(
+            Level::Notice,
+            format!("Skipping device operations: {}", reason),
+        );
+
+        match self.command {
+            Commands::Check(ref cmd) => cmd.run_unlit(settings),
+            _ => Ok(()),
+        }
+    }
+
+    /// Ask before changing a selection the `safety` config considers wide.
+    fn confirm(&self, settings: &Settings, devices: &Devices) -> Result<()> {
+        let safety = match settings.safety {
+            Some(ref safety) => safety,
+            None => return Ok(()),
+        };
+
+        let count = devices.devices.len();
+        let wide = (self.all && safety.confirm_all)
+            || safety.max_devices.map(|max| count > max).unwrap_or(false);
+
+        if !wide {
+            return Ok(());
+        }
+
+        println!("This will change {} devices:", count);
+        for device in devices.iter() {
+            println!("  {}", device.name);
+        }
+
+        match prompt("Continue? [y/N] ")?.to_lowercase().as_str() {
+            "y" | "yes" => Ok(()),
+            _ => bail!("Aborted, pass --yes to skip this confirmation"),
+        }
+    }
+
+    /// Drop devices held by a sticky alert from `devices`.
+    ///
+    /// Turning an alerted device off (from the govee app or a physical
+    /// switch) counts as acknowledging the alert, so those are cleared and
+    /// left in the selection instead.
+    async fn hold_alerted(&self, client: &Client, devices: &mut Devices) -> Result<()> {
+        let mut alerts = Alerts::load()?;
+
+        if alerts.devices.is_empty() {
+            return Ok(());
+        }
+
+        let mut held = HashSet::new();
+        let mut acknowledged = false;
+
+        for device in devices.iter() {
+            if !alerts.contains(&device.name) {
+                continue;
+            }
+
+            let turned_off = matches!(
+                client.snapshot(device).await,
+                Ok(DeviceSnapshot {
+                    power: Some(false),
+                    ..
+                })
+            );
+
+            if turned_off {
+                alerts.clear(&device.name);
+                acknowledged = true;
+                events::record("ack", format!("{} turned off by hand", device.name));
+            } else {
+                eprintln!(
+                    "Skipping {}: sticky alert active (clear with `spirit ack` or turn it off)",
+                    device.name
+                );
+                held.insert(device.name.clone());
+            }
+        }
+
+        if acknowledged {
+            alerts.save()?;
+        }
+
+        devices.devices.retain(|d| !held.contains(&d.name));
+
+        Ok(())
+    }
+
+    /// Every device known to the cloud api, from the state cache if the
+    /// command allows a cached list.
+    async fn list_devices(&self, client: &Client) -> Result<Devices> {
+        match self.command.device_list_ttl() {
+            Some(ttl) => StateCache::load()?.device_list(client, ttl).await,
+            None => client.devices().await,
+        }
     }
 
-    async fn get_devices(&self, client: &GoveeClient, settings: &Settings) -> Result<Devices> {
-        let mut devices = client.devices().await?;
+    async fn get_devices(
+        &self,
+        client: &Client,
+        settings: &Settings,
+        session: Option<&Session>,
+    ) -> Result<Devices> {
+        let mut devices = self.list_devices(client).await?;
+
+        // flags beat the session, which beats the environment, which beats
+        // the config
+        let flagged = self.all || !self.device.is_empty() || !self.group.is_empty();
+        let (all, names, groups) = match session {
+            _ if flagged => (self.all, self.device.clone(), self.group.clone()),
+            Some(session) if session.selects() => {
+                (session.all, session.devices.clone(), session.groups.clone())
+            }
+            _ => (false, env_list("SPIRIT_DEVICES"), env_list("SPIRIT_GROUP")),
+        };
+
+        if !all {
+            if !names.is_empty() || !groups.is_empty() {
+                let device_names: HashSet<&String> = names.iter().collect();
+                let device_settings = settings.device_settings();
 
-        if !self.all {
-            if !self.device.is_empty() {
-                let device_names: HashSet<&String> = self.device.iter().collect();
-                devices.devices.retain(|d| device_names.contains(&d.name));
+                devices.devices.retain(|d| {
+                    let setting = device_settings.get(&d.name);
+                    let alias = setting.and_then(|s| s.alias.as_ref());
+
+                    device_names.contains(&d.name)
+                        || alias.map(|a| device_names.contains(a)).unwrap_or(false)
+                        || setting.map(|s| s.in_any_group(&groups)).unwrap_or(false)
+                });
 
                 if devices.is_empty() {
                     bail!("No devices matched");
@@ -64,11 +369,28 @@ impl Cli {
 
                 Ok(devices)
             } else {
+                if settings.devices.is_none() {
+                    bail!("No devices configured, pass --device, --group or --all, set SPIRIT_DEVICES or add devices to spirit.toml");
+                }
+
                 let device_names = settings.device_settings();
 
-                devices
-                    .devices
-                    .retain(|d| device_names.get(&d.name).is_some());
+                devices.devices.retain(|d| {
+                    if device_names.get(&d.name).is_some() {
+                        return true;
+                    }
+
+                    match device_names.find_by_id(&d.device) {
+                        Some(setting) => {
+                            eprintln!(
+                                "Configured device '{}' is now named '{}' (fix with `spirit config fix-names`)",
+                                setting.name, d.name
+                            );
+                            true
+                        }
+                        None => false,
+                    }
+                });
 
                 if devices.is_empty() {
                     bail!("No devices matched");
@@ -85,39 +407,256 @@ impl Cli {
 #[derive(Subcommand)]
 pub enum Commands {
     Info(Info),
+    List(List),
     Toggle(Toggle),
     Check(Check),
+    #[command(subcommand)]
+    Scene(SceneCommands),
+    Ack(Ack),
+    Statusbar(Statusbar),
+    Serve(Serve),
+    Discover(Discover),
+    #[command(subcommand)]
+    Config(ConfigCommands),
+    Events(Events),
+    Health(HealthReport),
+    Flux(Flux),
+    Identify(Identify),
+    Adopt(Adopt),
+    Watch(Watch),
+    Keylight(Keylight),
+    AmbientDrift(AmbientDrift),
+    #[command(subcommand)]
+    Palette(PaletteCommands),
+    Signal(Signal),
+    #[command(subcommand)]
+    Service(ServiceCommands),
+    Play(Play),
+    Stream(Stream),
+    CiEnv(CiEnv),
+    #[command(subcommand)]
+    Session(SessionCommands),
+    #[command(subcommand)]
+    History(HistoryCommands),
+    #[command(subcommand)]
+    Report(ReportCommands),
+    #[cfg(feature = "hotkeys")]
+    Hotkeys(Hotkeys),
 }
 
 impl Commands {
-    pub async fn run(
-        &self,
-        client: &GoveeClient,
-        settings: &Settings,
-        devices: &Devices,
-    ) -> Result<()> {
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
         match self {
             Self::Info(cmd) => cmd.run(client, settings, devices).await,
+            Self::List(cmd) => cmd.run(client, settings, devices).await,
             Self::Toggle(cmd) => cmd.run(client, settings, devices).await,
             Self::Check(cmd) => cmd.run(client, settings, devices).await,
+            Self::Scene(cmd) => cmd.run(client, settings, devices).await,
+            Self::Ack(cmd) => cmd.run(client, settings, devices).await,
+            Self::Statusbar(cmd) => cmd.run(client, settings, devices).await,
+            Self::Serve(cmd) => cmd.run(client, settings, devices).await,
+            Self::Discover(cmd) => cmd.run(client, settings, devices).await,
+            Self::Config(cmd) => cmd.run(client, settings, devices).await,
+            Self::Events(cmd) => cmd.run().await,
+            Self::Health(cmd) => cmd.run(),
+            Self::Flux(cmd) => cmd.run(client, settings, devices).await,
+            Self::Identify(cmd) => cmd.run(client, settings, devices).await,
+            Self::Adopt(cmd) => cmd.run(client, settings, devices).await,
+            Self::Watch(cmd) => cmd.run(client, settings, devices).await,
+            Self::Keylight(cmd) => cmd.run(client, settings, devices).await,
+            Self::AmbientDrift(cmd) => cmd.run(client, settings, devices).await,
+            Self::Palette(cmd) => cmd.run(settings),
+            Self::History(cmd) => cmd.run(settings),
+            Self::Report(cmd) => cmd.run(client, settings, devices).await,
+            Self::Signal(cmd) => cmd.run(client, settings, devices).await,
+            Self::Play(cmd) => cmd.run(client, settings, devices).await,
+            Self::Stream(cmd) => cmd.run(client, settings, devices).await,
+            Self::Service(_) | Self::CiEnv(_) | Self::Session(_) => {
+                unreachable!("service, ci-env and session run before loading config")
+            }
+            #[cfg(feature = "hotkeys")]
+            Self::Hotkeys(cmd) => cmd.run(client, settings, devices).await,
+        }
+    }
+
+    /// Whether this command acts on the selected devices, rather than looking
+    /// at every device the account has.
+    pub fn uses_selection(&self) -> bool {
+        !matches!(self, Self::Discover(_) | Self::Config(_) | Self::Adopt(_))
+    }
+
+    /// Whether this command changes what the selected devices are doing,
+    /// as opposed to only reading them.
+    pub fn changes_state(&self) -> bool {
+        matches!(
+            self,
+            Self::Toggle(_)
+                | Self::Check(_)
+                | Self::Scene(SceneCommands::Apply(_))
+                | Self::Flux(_)
+                | Self::Keylight(_)
+                | Self::AmbientDrift(_)
+                | Self::Signal(_)
+                | Self::Play(_)
+                | Self::Stream(_)
+        )
+    }
+
+    /// How long a cached device list is good for, for commands run often
+    /// enough that listing devices every time would add up.
+    pub fn device_list_ttl(&self) -> Option<Duration> {
+        match self {
+            Self::Statusbar(cmd) => Some(Duration::from_secs(cmd.ttl)),
+            _ => None,
+        }
+    }
+
+    /// Whether this command reads the current state of every selected
+    /// device, so it's worth fetching them all up front.
+    pub fn needs_state(&self) -> bool {
+        match self {
+            Self::Info(_)
+            | Self::Scene(SceneCommands::Capture(_))
+            | Self::Identify(_)
+            | Self::Signal(_) => true,
+            Self::Check(cmd) => cmd.sticky,
+            Self::Scene(SceneCommands::Apply(cmd)) => cmd.fade.is_some(),
+            _ => false,
+        }
+    }
+
+    /// Whether devices held by a sticky alert should be left alone.
+    pub fn respects_alerts(&self) -> bool {
+        match self {
+            Self::Info(_)
+            | Self::List(_)
+            | Self::Ack(_)
+            | Self::Statusbar(_)
+            | Self::Discover(_)
+            | Self::Config(_)
+            | Self::Adopt(_)
+            | Self::Watch(_)
+            | Self::Events(_)
+            | Self::Health(_)
+            | Self::Report(_) => false,
+            Self::Scene(SceneCommands::Capture(_)) => false,
+            _ => true,
         }
     }
 }
 
 /// Display info about a set of devices.
+///
+/// States are fetched concurrently, sharing the api rate budget. Devices the
+/// api reports as not retrievable are skipped without a request.
 #[derive(Args)]
-pub struct Info;
+pub struct Info {
+    /// The maximum number of states to fetch at once.
+    #[arg(long, default_value_t = 4)]
+    concurrency: usize,
+
+    /// Print each device with this template instead.
+    ///
+    /// Available variables are name, id, model, kind, notes, location and state
+    /// (with power, brightness and color), e.g. '{{name}}: {{state.power}}'.
+    #[arg(short, long)]
+    template: Option<String>,
+}
 
 impl Info {
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
+        let template = self.template.as_deref().map(Template::new).transpose()?;
+
+        let states: Vec<Option<Result<DeviceSnapshot>>> = stream::iter(devices.iter())
+            .map(|device| async move {
+                if device.retrievable {
+                    Some(client.snapshot(device).await)
+                } else {
+                    None
+                }
+            })
+            .buffered(self.concurrency.max(1))
+            .collect()
+            .await;
+
+        let device_settings = settings.device_settings();
+        let names = ColorNames::new(settings);
+
+        for (device, state) in devices.iter().zip(states) {
+            let setting = device_settings.get(&device.name);
+
+            // one unreachable device shouldn't hide the rest
+            let state = state
+                .map(|s| s.map_err(|e| eprintln!("{}: could not get state: {:#}", device.name, e)));
+
+            if let Some(ref template) = template {
+                let state = state.and_then(|s| s.ok());
+                println!(
+                    "{}",
+                    template.render(DeviceContext::new(device, setting, state.as_ref()))?
+                );
+                continue;
+            }
+
+            if let Some(description) = setting.and_then(|s| s.describe()) {
+                println!("# {}", description);
+            }
+
+            match state {
+                Some(Ok(mut snapshot)) => {
+                    snapshot.color = snapshot.color.map(|c| names.describe(&c));
+                    println!("{}: {:#?}", device.name, snapshot)
+                }
+                Some(Err(())) => {}
+                None => println!("{}: state not retrievable", device.name),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// List a set of devices without querying their state.
+#[derive(Args)]
+pub struct List {
+    /// Print each device with this template instead.
+    ///
+    /// Available variables are name, id, model, kind, notes and location.
+    #[arg(short, long)]
+    template: Option<String>,
+}
+
+impl List {
     pub async fn run(
         &self,
-        client: &GoveeClient,
-        _settings: &Settings,
+        _client: &Client,
+        settings: &Settings,
         devices: &Devices,
     ) -> Result<()> {
+        let template = self.template.as_deref().map(Template::new).transpose()?;
+        let device_settings = settings.device_settings();
+
         for device in devices.iter() {
-            println!("{:#?}", client.state(device).await?);
+            let setting = device_settings.get(&device.name);
+
+            if let Some(ref template) = template {
+                println!(
+                    "{}",
+                    template.render(DeviceContext::new(device, setting, None))?
+                );
+                continue;
+            }
+
+            println!(
+                "{:<24} {:<8} {:<6} {:<24} {}",
+                device.name,
+                device.model,
+                Kind::of(device).as_str(),
+                device.device,
+                setting.and_then(|s| s.describe()).unwrap_or_default()
+            );
         }
+
         Ok(())
     }
 }
@@ -139,31 +678,36 @@ pub struct Toggle {
 }
 
 impl Toggle {
-    pub async fn run(
-        &self,
-        client: &GoveeClient,
-        settings: &Settings,
-        devices: &Devices,
-    ) -> Result<()> {
-        if self.off {
-            for device in devices.iter() {
-                client.turn(device, PowerState::Off).await?;
-            }
-
-            return Ok(());
-        }
-
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
         let device_settings = settings.device_settings();
 
         let force = self.color.as_deref();
         let default = settings.default.as_deref();
 
-        for device in devices.iter() {
-            if let Some(color) = device_settings.default_color(&device.name, force, default)? {
-                client.color(device, color).await?;
-            } else {
-                client.turn(device, PowerState::On).await?;
-            }
+        let results = client
+            .each(devices.iter(), |client, device| {
+                let color = if self.off || !capability::has_color(&device) {
+                    Ok(None)
+                } else {
+                    device_settings.default_color(&device.name, force, default)
+                };
+
+                let off = self.off;
+                async move {
+                    if off {
+                        return client.turn(&device, PowerState::Off).await;
+                    }
+
+                    match color? {
+                        Some(color) => client.color(&device, color).await,
+                        None => client.turn(&device, PowerState::On).await,
+                    }
+                }
+            })
+            .await;
+
+        for (_, res) in results {
+            res?;
         }
 
         Ok(())
@@ -174,6 +718,9 @@ impl Toggle {
 ///
 /// This is binary decision where the success color corresponds to exit code 0
 /// and the fail color to all other exit codes.
+///
+/// Checks defined as `[[checks]]` in the config can be run by name instead,
+/// which narrows the selected devices to the check's devices and groups.
 #[derive(Args)]
 pub struct Check {
     /// Set this color on success.
@@ -184,39 +731,2047 @@ pub struct Check {
     #[arg(short, long, env = "SPIRIT_FAIL_COLOR")]
     fail: Option<String>,
 
+    /// Keep the fail color until cleared with `spirit ack`.
+    ///
+    /// Other commands will skip devices in this state. Turning a device off
+    /// by hand also clears it.
+    #[arg(long)]
+    sticky: bool,
+
+    /// Run the check with this name from the config.
+    #[arg(short, long, conflicts_with = "cmd")]
+    name: Option<String>,
+
+    /// Read structured test results from the command's output, or from
+    /// stdin if there is no command.
+    ///
+    /// The json events are consumed; any other output is passed through to
+    /// stdout.
+    ///
+    /// More failures shade from amber toward the fail color, tests that
+    /// only passed on retry show amber, and --sticky only holds for real
+    /// failures.
+    #[arg(long, value_enum)]
+    parse: Option<ParseMode>,
+
+    /// The number of failures that gets the full fail color with --parse.
+    #[arg(long, default_value_t = 10)]
+    gradient: usize,
+
     /// The command to run
-    #[arg(last = true)]
+    #[arg(last = true, required_unless_present_any = ["name", "parse"])]
     cmd: Vec<String>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ParseMode {
+    /// `cargo nextest run --message-format libtest-json`
+    NextestJson,
+}
+
+/// How a check went, as far as the lights are concerned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Outcome {
+    Passed,
+    /// Failed, with how badly from 0 to 1 when that's known.
+    Failed(Option<f64>),
+    /// Passed, but only after retrying failures.
+    Flaky,
+}
+
 impl Check {
-    pub async fn run(
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
+        let ci = ci::Environment::detect();
+        let check = self.find(settings)?;
+
+        let notifiers = Notifiers::from_settings(settings)?;
+
+        let mut targets = devices.clone();
+        if let Some(check) = check {
+            if check.devices.is_some() || check.groups.is_some() {
+                let names = check.devices.clone().unwrap_or_default();
+                let groups = check.groups.clone().unwrap_or_default();
+                let device_settings = settings.device_settings();
+
+                targets.devices.retain(|d| {
+                    let setting = device_settings.get(&d.name);
+                    let alias = setting.and_then(|s| s.alias.as_ref());
+
+                    names.contains(&d.name)
+                        || alias.map(|a| names.contains(a)).unwrap_or(false)
+                        || setting.map(|s| s.in_any_group(&groups)).unwrap_or(false)
+                });
+
+                if targets.devices.is_empty() {
+                    bail!("No selected devices matched check '{}'", check.name);
+                }
+            }
+        }
+
+        let (code, outcome) = self.execute(check, &ci)?;
+
+        let success = self
+            .success
+            .as_deref()
+            .or_else(|| check.and_then(|c| c.success.as_deref()))
+            .or(Some(settings.success.as_str()));
+        let fail = self
+            .fail
+            .as_deref()
+            .or_else(|| check.and_then(|c| c.fail.as_deref()))
+            .or(Some(settings.fail.as_str()));
+
+        // the lights are a side show, so failing to update them shouldn't
+        // hide the command's own exit code
+        if let Err(e) = self
+            .update(client, settings, &targets, outcome, success, fail)
+            .await
+        {
+            if ci.is_ci() {
+                ci.annotate(Level::Warning, format!("Could not update devices: {:#}", e));
+            } else {
+                eprintln!("Could not update devices: {:#}", e);
+            }
+        }
+
+        if let Some(table) = client.result_summary(&targets) {
+            eprintln!("{}", table);
+        }
+
+        if let Some(summary) = client.stop_summary(&targets) {
+            eprintln!("{}", summary);
+        }
+
+        let label = self.label(check);
+        let result = Self::describe(outcome);
+        let event = match outcome {
+            Outcome::Failed(_) => Event::CheckFailed,
+            _ => Event::CheckPassed,
+        };
+        notifiers
+            .notify(Notification::new(
+                event,
+                format!("{} {}", label, result),
+                format!("'{}' {} (exit code {})", label, result, code),
+            ))
+            .await;
+
+        self.annotate_result(&ci, &label, outcome, code);
+
+        // exiting skips the client's drop
+        client.flush_health();
+        std::process::exit(code);
+    }
+
+    /// Run the check without any devices, for when `ci-env` says to skip
+    /// them. Notifiers are skipped along with the lights.
+    pub fn run_unlit(&self, settings: &Settings) -> Result<()> {
+        let ci = ci::Environment::detect();
+        let check = self.find(settings)?;
+
+        let (code, outcome) = self.execute(check, &ci)?;
+        self.annotate_result(&ci, &self.label(check), outcome, code);
+
+        std::process::exit(code);
+    }
+
+    fn find<'a>(&self, settings: &'a Settings) -> Result<Option<&'a CheckSetting>> {
+        Ok(match self.name {
+            Some(ref name) => Some(
+                settings
+                    .checks
+                    .iter()
+                    .flatten()
+                    .find(|c| c.name == *name)
+                    .ok_or_else(|| anyhow!("No check named '{}' in config", name))?,
+            ),
+            None => None,
+        })
+    }
+
+    /// Run the command (or read results from stdin) and work out how it went.
+    fn execute(
         &self,
-        client: &GoveeClient,
-        settings: &Settings,
-        devices: &Devices,
-    ) -> Result<()> {
-        let success = self.success.as_deref();
-        let fail = self.fail.as_deref();
+        check: Option<&CheckSetting>,
+        ci: &ci::Environment,
+    ) -> Result<(i32, Outcome)> {
+        let command = match check {
+            Some(check) => {
+                let mut command = Command::new("sh");
+                command.arg("-c").arg(&check.command);
+                Some(command)
+            }
+            None if !self.cmd.is_empty() => {
+                let parsed: Vec<&String> = self.cmd.iter().collect();
 
-        let device_settings = settings.device_settings();
+                let (cmd, args) = parsed.split_first().expect("command was empty");
 
-        let parsed: Vec<&String> = self.cmd.iter().collect();
+                let mut command = Command::new(cmd);
+                command.args(args);
+                Some(command)
+            }
+            None => None,
+        };
 
-        let (cmd, args) = parsed.split_first().expect("command was empty");
+        Ok(match (self.parse, command) {
+            (None, Some(mut command)) => {
+                let res = command.status()?;
+                let outcome = if res.success() {
+                    Outcome::Passed
+                } else {
+                    Outcome::Failed(None)
+                };
+                (res.code().expect("could not get status code"), outcome)
+            }
+            (Some(ParseMode::NextestJson), Some(mut command)) => {
+                let mut child = command.stdout(Stdio::piped()).spawn()?;
+                let stdout = child.stdout.take().expect("stdout is piped");
+                let summary = Summary::parse(io::BufReader::new(stdout), io::stdout())?;
+                let res = child.wait()?;
 
-        let res = Command::new(cmd).args(args).status()?;
+                let outcome = self.grade(ci, &summary, res.success());
+                (res.code().expect("could not get status code"), outcome)
+            }
+            (Some(ParseMode::NextestJson), None) => {
+                let summary = Summary::parse(io::stdin().lock(), io::stdout())?;
 
-        for device in devices.iter() {
-            let color = if res.success() {
-                device_settings.success_color(&device.name, success)?
+                let outcome = self.grade(ci, &summary, true);
+                let code = if summary.failed.is_empty() { 0 } else { 1 };
+                (code, outcome)
+            }
+            (None, None) => unreachable!("clap requires a command, name or parse mode"),
+        })
+    }
+
+    fn label(&self, check: Option<&CheckSetting>) -> String {
+        match check {
+            Some(check) => check.name.clone(),
+            None if self.cmd.is_empty() => "test results".to_string(),
+            None => self.cmd.join(" "),
+        }
+    }
+
+    fn describe(outcome: Outcome) -> &'static str {
+        match outcome {
+            Outcome::Passed => "passed",
+            Outcome::Flaky => "passed with flaky tests",
+            Outcome::Failed(_) => "failed",
+        }
+    }
+
+    /// Leave a CI annotation for anything other than a clean pass.
+    fn annotate_result(&self, ci: &ci::Environment, label: &str, outcome: Outcome, code: i32) {
+        if !ci.is_ci() {
+            return;
+        }
+
+        let level = match outcome {
+            Outcome::Passed => return,
+            Outcome::Flaky => Level::Warning,
+            Outcome::Failed(_) => Level::Error,
+        };
+
+        ci.annotate(
+            level,
+            format!(
+                "'{}' {} (exit code {})",
+                label,
+                Self::describe(outcome),
+                code
+            ),
+        );
+    }
+
+    /// The color `outcome` calls for on the device called `name`.
+    fn outcome_color(
+        device_settings: &DeviceSettingMap,
+        name: &str,
+        outcome: Outcome,
+        success: Option<&str>,
+        fail: Option<&str>,
+    ) -> Result<Color> {
+        let color = match outcome {
+            Outcome::Passed => device_settings.success_color(name, success)?,
+            Outcome::Flaky => Some(Self::WARN_COLOR.to_color()?),
+            Outcome::Failed(None) => device_settings.fail_color(name, fail)?,
+            Outcome::Failed(Some(severity)) => match device_settings.fail_color(name, fail)? {
+                Some(full) => Some(
+                    Self::WARN_COLOR
+                        .lerp(Rgb::new(full.r, full.g, full.b), severity)
+                        .to_color()?,
+                ),
+                None => None,
+            },
+        };
+
+        Ok(color.unwrap())
+    }
+
+    const WARN_COLOR: Rgb = Rgb {
+        r: 0xff,
+        g: 0xa5,
+        b: 0x00,
+    };
+
+    /// Turn parsed test results into an outcome. A run that failed without
+    /// reporting any failed tests (like a build error) is a plain failure.
+    fn grade(&self, ci: &ci::Environment, summary: &Summary, succeeded: bool) -> Outcome {
+        let failed = summary.failed.len();
+
+        println!(
+            "{} passed, {} failed, {} flaky",
+            summary.passed,
+            failed,
+            summary.flaky.len()
+        );
+        for name in summary.failed.iter() {
+            if ci.is_ci() {
+                ci.annotate(Level::Error, format!("test failed: {}", name));
+            } else {
+                println!("  failed: {}", name);
+            }
+        }
+        for name in summary.flaky.iter() {
+            if ci.is_ci() {
+                ci.annotate(Level::Warning, format!("flaky test: {}", name));
             } else {
-                device_settings.fail_color(&device.name, fail)?
+                println!("  flaky: {}", name);
+            }
+        }
+
+        if failed > 0 {
+            let steps = self.gradient.max(2) - 1;
+            let severity = (failed - 1).min(steps) as f64 / steps as f64;
+            Outcome::Failed(Some(severity))
+        } else if !succeeded {
+            Outcome::Failed(None)
+        } else if !summary.flaky.is_empty() {
+            Outcome::Flaky
+        } else {
+            Outcome::Passed
+        }
+    }
+
+    async fn update(
+        &self,
+        client: &Client,
+        settings: &Settings,
+        devices: &Devices,
+        outcome: Outcome,
+        success: Option<&str>,
+        fail: Option<&str>,
+    ) -> Result<()> {
+        let device_settings = settings.device_settings();
+        let failed = matches!(outcome, Outcome::Failed(_));
+
+        let mut alerts = if self.sticky && failed {
+            Some(Alerts::load()?)
+        } else {
+            None
+        };
+
+        let sticky = alerts.is_some();
+        let results = client
+            .each(devices.iter(), |client, device| {
+                // a switch (or a light that only dims) can't show a color, so
+                // it comes on to signal a failure
+                let color = if capability::has_color(&device) {
+                    Self::outcome_color(&device_settings, &device.name, outcome, success, fail)
+                        .map(Some)
+                } else {
+                    Ok(None)
+                };
+
+                async move {
+                    let color = color?;
+                    let previous = if sticky {
+                        Some(client.snapshot(&device).await?)
+                    } else {
+                        None
+                    };
+
+                    match color {
+                        Some(color) => client.color(&device, color).await?,
+                        None if failed => client.turn(&device, PowerState::On).await?,
+                        None => client.turn(&device, PowerState::Off).await?,
+                    }
+
+                    Ok::<_, anyhow::Error>(previous)
+                }
+            })
+            .await;
+
+        let mut res = Ok(());
+        for (device, result) in results {
+            match result {
+                Ok(previous) => {
+                    if let (Some(alerts), Some(previous)) = (alerts.as_mut(), previous) {
+                        alerts.raise(&device.name, previous);
+                    }
+                }
+                Err(e) if res.is_ok() => res = Err(e),
+                Err(_) => {}
+            }
+        }
+
+        // keep the restore points for whatever was changed before another
+        // device failed or the command was cancelled, so `ack` can still put
+        // those devices back
+        if let Some(alerts) = alerts {
+            alerts.save()?;
+        }
+
+        res
+    }
+}
+
+/// Capture and replay named scenes.
+#[derive(Subcommand)]
+pub enum SceneCommands {
+    Capture(SceneCapture),
+    Apply(SceneApply),
+}
+
+impl SceneCommands {
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
+        match self {
+            Self::Capture(cmd) => cmd.run(client, settings, devices).await,
+            Self::Apply(cmd) => cmd.run(client, settings, devices).await,
+        }
+    }
+}
+
+/// Save the current state of a set of devices as a named scene.
+///
+/// Existing scenes with the same name are replaced.
+#[derive(Args)]
+pub struct SceneCapture {
+    /// The name to save the scene under.
+    name: String,
+
+    /// The scenes file to write to.
+    ///
+    /// Defaults to spirit-scenes.toml in the home directory.
+    #[arg(long, env = "SPIRIT_SCENES_FILE")]
+    file: Option<PathBuf>,
+}
+
+impl SceneCapture {
+    pub async fn run(
+        &self,
+        client: &Client,
+        _settings: &Settings,
+        devices: &Devices,
+    ) -> Result<()> {
+        let path = match self.file {
+            Some(ref path) => path.clone(),
+            None => Scenes::default_path()?,
+        };
+
+        let mut scenes = Scenes::load(&path)?;
+        let mut scene = Scene::new();
+
+        for device in devices.iter() {
+            scene.insert(device.name.clone(), client.snapshot(device).await?);
+        }
+
+        scenes.insert(&self.name, scene);
+        scenes.save(&path)?;
+
+        println!("Captured scene '{}' to {}", self.name, path.display());
+
+        Ok(())
+    }
+}
+
+/// Restore a previously captured scene.
+///
+/// Only devices that are both part of the scene and selected are affected.
+#[derive(Args)]
+pub struct SceneApply {
+    /// The name of the scene to apply.
+    name: String,
+
+    /// The scenes file to read from.
+    ///
+    /// Defaults to spirit-scenes.toml in the home directory.
+    #[arg(long, env = "SPIRIT_SCENES_FILE")]
+    file: Option<PathBuf>,
+
+    /// Crossfade from the current state over this long (e.g. 30s, 10m)
+    /// instead of switching at once.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    fade: Option<Duration>,
+
+    /// How many increments a crossfade takes.
+    #[arg(long, default_value_t = 10)]
+    steps: u32,
+}
+
+impl SceneApply {
+    pub async fn run(
+        &self,
+        client: &Client,
+        _settings: &Settings,
+        devices: &Devices,
+    ) -> Result<()> {
+        let path = match self.file {
+            Some(ref path) => path.clone(),
+            None => Scenes::default_path()?,
+        };
+
+        let scenes = Scenes::load(&path)?;
+        let scene = scenes
+            .get(&self.name)
+            .ok_or_else(|| anyhow!("No scene named '{}' in {}", self.name, path.display()))?;
+
+        if let Some(fade) = self.fade {
+            return scene::crossfade(client, devices, scene, fade, self.steps).await;
+        }
+
+        for device in devices.iter() {
+            if let Some(snapshot) = scene.get(&device.name) {
+                snapshot.apply(client, device).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BarFormat {
+    /// A single line of plain text.
+    Plain,
+    /// A waybar custom module JSON object.
+    Waybar,
+    /// Polybar formatting tags.
+    Polybar,
+    /// The i3blocks full_text/short_text/color lines.
+    I3blocks,
+}
+
+/// Print a status bar segment summarizing a set of devices.
+///
+/// Shows a dot per device in its current color (grey when off) and a count
+/// of powered devices. States are cached between runs to avoid spending API
+/// quota on every bar refresh.
+#[derive(Args)]
+pub struct Statusbar {
+    /// The output format.
+    #[arg(short, long, value_enum, default_value_t = BarFormat::Plain)]
+    format: BarFormat,
+
+    /// Seconds before a cached device state, or the cached device list, is
+    /// refreshed.
+    #[arg(long, default_value_t = 60)]
+    ttl: u64,
+
+    /// A command to run when the segment is clicked.
+    ///
+    /// For polybar this is embedded as an action tag, for i3blocks it is run
+    /// when BLOCK_BUTTON is set. Waybar handles clicks in its own config.
+    #[arg(long)]
+    on_click: Option<String>,
+}
+
+impl Statusbar {
+    const OFF_COLOR: &'static str = "#555555";
+
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
+        if self.format == BarFormat::I3blocks {
+            if let (Some(cmd), Ok(button)) = (&self.on_click, std::env::var("BLOCK_BUTTON")) {
+                if !button.is_empty() {
+                    Command::new("sh").arg("-c").arg(cmd).status()?;
+                }
+            }
+        }
+
+        let mut cache = StateCache::load()?;
+        let snapshots = cache
+            .refresh(client, devices, Duration::from_secs(self.ttl))
+            .await?;
+
+        let total = snapshots.len();
+        let powered = snapshots.iter().filter(|s| s.power == Some(true)).count();
+        let colors: Vec<&str> = snapshots
+            .iter()
+            .map(|s| match (s.power, s.color.as_deref()) {
+                (Some(true), Some(color)) => color,
+                _ => Self::OFF_COLOR,
+            })
+            .collect();
+
+        let count = format!("{}/{}", powered, total);
+
+        match self.format {
+            BarFormat::Plain => println!("{} on", count),
+            BarFormat::Waybar => {
+                let dots: Vec<String> = colors
+                    .iter()
+                    .map(|c| format!("<span color='{}'>●</span>", c))
+                    .collect();
+                let now = cache::now();
+                let names = ColorNames::new(settings);
+                let tooltip: Vec<String> = devices
+                    .iter()
+                    .zip(snapshots.iter())
+                    .map(|(d, s)| {
+                        let state = match (s.online, s.power, s.color.as_deref()) {
+                            (Some(false), _, _) => "offline".to_string(),
+                            (_, Some(true), Some(color)) => format!("on {}", names.describe(color)),
+                            (_, Some(true), None) => "on".to_string(),
+                            _ => "off".to_string(),
+                        };
+                        let changed = cache
+                            .devices
+                            .get(&d.name)
+                            .and_then(|c| c.changed)
+                            .map(|at| {
+                                let ago = Duration::from_secs(now.saturating_sub(at));
+                                format!(" (changed {} ago)", humantime::format_duration(ago))
+                            })
+                            .unwrap_or_default();
+                        format!("{}: {}{}", d.name, state, changed)
+                    })
+                    .collect();
+                let class = if powered > 0 { "on" } else { "off" };
+                let segment = serde_json::json!({
+                    "text": format!("{} {}", dots.join(" "), count),
+                    "tooltip": tooltip.join("\n"),
+                    "class": class,
+                });
+                println!("{}", segment);
+            }
+            BarFormat::Polybar => {
+                let dots: Vec<String> = colors
+                    .iter()
+                    .map(|c| format!("%{{F{}}}●%{{F-}}", c))
+                    .collect();
+                let segment = format!("{} {}", dots.join(" "), count);
+                match self.on_click {
+                    Some(ref cmd) => {
+                        println!("%{{A1:{}:}}{}%{{A}}", cmd.replace(':', "\\:"), segment)
+                    }
+                    None => println!("{}", segment),
+                }
+            }
+            BarFormat::I3blocks => {
+                println!("{} on", count);
+                println!("{}", count);
+                if let Some(color) = colors.iter().find(|c| **c != Self::OFF_COLOR) {
+                    println!("{}", color);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Expose configured buttons and device state as local http endpoints.
+///
+/// Each entry in the `buttons` section of the config is triggered by a
+/// request to `/press/<name>`, which makes wiring up Stream Deck style
+/// controllers a matter of pasting urls. `/state` reports device state from
+/// the state cache `spirit watch` keeps up to date, and tokens in the `serve`
+/// section can restrict clients to reading it. Runs until interrupted.
+#[derive(Args)]
+pub struct Serve {
+    /// The address to listen on.
+    #[arg(long, default_value = "127.0.0.1:7878", env = "SPIRIT_LISTEN")]
+    listen: SocketAddr,
+
+    /// Seconds before a cached device state is refreshed.
+    #[arg(long, default_value_t = 60)]
+    ttl: u64,
+}
+
+impl Serve {
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
+        crate::serve::serve(
+            client,
+            settings,
+            devices,
+            self.listen,
+            Duration::from_secs(self.ttl),
+        )
+        .await
+    }
+}
+
+/// Find devices on the local network via the govee LAN api.
+///
+/// Devices must have the LAN control option enabled in the govee app to
+/// respond. Found devices are matched against the cloud device list by id to
+/// get their names.
+#[derive(Args)]
+pub struct Discover {
+    /// Seconds to wait for responses.
+    #[arg(long, default_value_t = 3)]
+    wait: u64,
+
+    /// Append entries for found devices that are not yet configured to the
+    /// config file.
+    #[arg(long)]
+    merge: bool,
+}
+
+impl Discover {
+    pub async fn run(
+        &self,
+        _client: &Client,
+        settings: &Settings,
+        devices: &Devices,
+    ) -> Result<()> {
+        let found = LanClient::default()
+            .scan(Duration::from_secs(self.wait))
+            .await?;
+
+        if found.is_empty() {
+            println!("No devices responded");
+            return Ok(());
+        }
+
+        let device_settings = settings.device_settings();
+        let mut entries = Vec::new();
+
+        for lan_device in found.iter() {
+            let name = devices
+                .iter()
+                .find(|d| d.device == lan_device.device)
+                .map(|d| d.name.as_str());
+
+            println!(
+                "{:<16} {:<8} {:<24} {}",
+                lan_device.ip,
+                lan_device.sku,
+                lan_device.device,
+                name.unwrap_or("(unknown)")
+            );
+
+            if let Some(name) = name {
+                if device_settings.get(name).is_none() {
+                    entries.push(NewDevice {
+                        name: name.to_string(),
+                        id: lan_device.device.clone(),
+                        transport: Some("lan".to_string()),
+                        ip: Some(lan_device.ip.to_string()),
+                        ..Default::default()
+                    });
+                }
+            }
+        }
+
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        if self.merge {
+            let path = Settings::writable_path()?;
+            append_devices(&path, &entries)?;
+            println!("\nAdded new devices to {}", path.display());
+        } else {
+            println!("\nUnconfigured devices (add to spirit.toml or rerun with --merge):\n");
+            print!("{}", toml::to_string(&NewDevices { devices: entries })?);
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct NewDevices {
+    devices: Vec<NewDevice>,
+}
+
+#[derive(Default, Serialize)]
+struct NewDevice {
+    name: String,
+    id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alias: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    groups: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transport: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ip: Option<String>,
+}
+
+impl NewDevice {
+    fn to_table(&self) -> toml_edit::Table {
+        let mut table = toml_edit::Table::new();
+        table["name"] = toml_edit::value(self.name.as_str());
+        table["id"] = toml_edit::value(self.id.as_str());
+
+        if let Some(ref alias) = self.alias {
+            table["alias"] = toml_edit::value(alias.as_str());
+        }
+        if !self.groups.is_empty() {
+            table["groups"] = toml_edit::value(self.groups.iter().collect::<toml_edit::Array>());
+        }
+        if let Some(ref transport) = self.transport {
+            table["transport"] = toml_edit::value(transport.as_str());
+        }
+        if let Some(ref ip) = self.ip {
+            table["ip"] = toml_edit::value(ip.as_str());
+        }
+
+        table
+    }
+}
+
+/// Add `new` to the `devices` list in the config at `path`, keeping the rest
+/// of the file as it is and whichever form the list is already written in.
+fn append_devices(path: &Path, new: &[NewDevice]) -> Result<()> {
+    let raw = if path.exists() {
+        fs::read_to_string(path)?
+    } else {
+        String::new()
+    };
+    let mut doc = raw
+        .parse::<toml_edit::Document>()
+        .with_context(|| format!("Could not parse {}", path.display()))?;
+
+    let devices = doc
+        .entry("devices")
+        .or_insert_with(|| toml_edit::Item::ArrayOfTables(toml_edit::ArrayOfTables::new()));
+
+    match devices {
+        toml_edit::Item::ArrayOfTables(tables) => {
+            for device in new {
+                tables.push(device.to_table());
+            }
+        }
+        toml_edit::Item::Value(toml_edit::Value::Array(array)) => {
+            for device in new {
+                array.push(device.to_table().into_inline_table());
+            }
+        }
+        _ => bail!("`devices` in {} is not a list", path.display()),
+    }
+
+    fs::write(path, doc.to_string()).with_context(|| format!("Could not write {}", path.display()))
+}
+
+/// Inspect and maintain config files.
+#[derive(Subcommand)]
+pub enum ConfigCommands {
+    FixNames(FixNames),
+    Lint(ConfigLint),
+}
+
+impl ConfigCommands {
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
+        match self {
+            Self::FixNames(cmd) => cmd.run(client, settings, devices).await,
+            Self::Lint(cmd) => cmd.run(client, settings, devices).await,
+        }
+    }
+}
+
+/// Report config that can never take effect or contradicts itself.
+///
+/// This covers references to unknown devices and groups, auto_off rules
+/// that can never fire or that disagree with each other, invalid flux
+/// curves and duplicate names. Exits with an error if any errors are found.
+#[derive(Args)]
+pub struct ConfigLint {}
+
+impl ConfigLint {
+    pub async fn run(
+        &self,
+        _client: &Client,
+        settings: &Settings,
+        devices: &Devices,
+    ) -> Result<()> {
+        let findings = lint::lint(settings, devices);
+
+        for finding in findings.iter() {
+            println!("{}: {}", finding.severity, finding.message);
+        }
+
+        let errors = findings
+            .iter()
+            .filter(|f| f.severity == Severity::Error)
+            .count();
+
+        if errors > 0 {
+            bail!("Found {} error(s) in config", errors);
+        }
+
+        if findings.is_empty() {
+            println!("No issues found");
+        }
+
+        Ok(())
+    }
+}
+
+/// Rewrite configured device names to match the names in the govee app.
+///
+/// Devices are matched by their `id`. Entries without an `id` whose name
+/// still matches have the id filled in so future renames can be fixed.
+#[derive(Args)]
+pub struct FixNames {
+    /// Show what would change without writing anything.
+    #[arg(long)]
+    dry_run: bool,
+}
+
+impl FixNames {
+    pub async fn run(
+        &self,
+        _client: &Client,
+        _settings: &Settings,
+        devices: &Devices,
+    ) -> Result<()> {
+        for path in Settings::config_paths() {
+            let raw = fs::read_to_string(&path)?;
+            let mut doc = raw
+                .parse::<toml_edit::Document>()
+                .with_context(|| format!("Could not parse {}", path.display()))?;
+
+            let mut changed = false;
+
+            if let Some(entries) = doc
+                .get_mut("devices")
+                .and_then(|d| d.as_array_of_tables_mut())
+            {
+                for entry in entries.iter_mut() {
+                    let name = entry.get("name").and_then(|v| v.as_str()).map(String::from);
+                    let id = entry.get("id").and_then(|v| v.as_str()).map(String::from);
+
+                    match (name, id) {
+                        (Some(name), Some(id)) => {
+                            if let Some(device) = devices.iter().find(|d| d.device == id) {
+                                if device.name != name {
+                                    println!("{}: {} -> {}", path.display(), name, device.name);
+                                    entry["name"] = toml_edit::value(device.name.as_str());
+                                    changed = true;
+                                }
+                            }
+                        }
+                        (Some(name), None) => {
+                            if let Some(device) = devices.iter().find(|d| d.name == name) {
+                                println!("{}: {} id = {}", path.display(), name, device.device);
+                                entry["id"] = toml_edit::value(device.device.as_str());
+                                changed = true;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            if changed && !self.dry_run {
+                fs::write(&path, doc.to_string())
+                    .with_context(|| format!("Could not write {}", path.display()))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Show the event log written by long-running modes like serve and hotkeys.
+#[derive(Args)]
+pub struct Events {
+    /// Keep printing new events as they are written.
+    #[arg(short, long)]
+    follow: bool,
+
+    /// The number of past events to show.
+    #[arg(short = 'n', long, default_value_t = 20)]
+    lines: usize,
+}
+
+impl Events {
+    pub async fn run(&self) -> Result<()> {
+        events::dump(self.lines, self.follow).await
+    }
+}
+
+/// Report devices that frequently fail.
+///
+/// Every device operation spirit performs is recorded, keeping the most
+/// recent outcomes per device. Devices failing more often than the threshold
+/// are flagged.
+#[derive(Args)]
+pub struct HealthReport {
+    /// Flag devices whose failure rate is at or above this fraction.
+    #[arg(long, default_value_t = 0.2)]
+    threshold: f64,
+}
+
+impl HealthReport {
+    pub fn run(&self) -> Result<()> {
+        let summaries = Health::load()?.summaries();
+
+        if summaries.is_empty() {
+            println!("No device operations recorded yet");
+            return Ok(());
+        }
+
+        println!(
+            "{:<24} {:>6} {:>6} {:>6}  last error",
+            "device", "ops", "failed", "rate"
+        );
+
+        for summary in summaries.iter() {
+            let flag = if summary.failure_rate() >= self.threshold {
+                " !"
+            } else {
+                ""
+            };
+
+            println!(
+                "{:<24} {:>6} {:>6} {:>5.0}%{}  {}",
+                summary.name,
+                summary.total,
+                summary.failed,
+                summary.failure_rate() * 100.0,
+                flag,
+                summary.last_error.as_deref().unwrap_or("-")
+            );
+        }
+
+        if summaries.len() > 1 && summaries.iter().all(|s| s.failure_rate() >= self.threshold) {
+            println!(
+                "\nEvery device is failing, which points at the api or network rather than individual devices."
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Continuously shift a set of devices between cool and warm white over the
+/// course of the day.
+///
+/// The curve comes from the `flux` entries in the config (time and kelvin
+/// pairs), falling back to cool at midday and warm in the evening. Devices
+/// that take a color temperature over the LAN are sent it, the rest get an
+/// rgb approximation of each white point.
+#[derive(Args)]
+pub struct Flux {
+    /// Seconds between adjustments.
+    #[arg(long, default_value_t = 300)]
+    interval: u64,
+
+    /// Apply the current temperature once and exit.
+    #[arg(long)]
+    once: bool,
+}
+
+impl Flux {
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
+        let devices = &capability::white_lights(devices);
+
+        let curve = match settings.flux {
+            Some(ref points) => Curve::from_settings(points)?,
+            None => Curve::default_curve(),
+        };
+
+        let mut last = None;
+
+        loop {
+            let kelvin = curve.kelvin_at(Local::now().time());
+
+            if last != Some(kelvin) {
+                events::record("flux", format!("{}K", kelvin));
+
+                for device in devices.iter() {
+                    if let Err(e) = client.color_temperature(device, kelvin).await {
+                        if self.once {
+                            return Err(e);
+                        }
+                        events::record("error", format!("flux {}: {:#}", device.name, e));
+                        eprintln!("Could not set {}: {:#}", device.name, e);
+                    }
+                }
+
+                last = Some(kelvin);
+            }
+
+            if self.once {
+                return Ok(());
+            }
+
+            tokio::time::sleep(Duration::from_secs(self.interval)).await;
+        }
+    }
+}
+
+/// Flash a set of devices one at a time to find out which is which.
+///
+/// Each device is restored to its previous state afterwards. Typically used
+/// with --device to pick out a single light.
+#[derive(Args)]
+pub struct Identify {
+    /// The number of flashes.
+    #[arg(long, default_value_t = 3)]
+    times: usize,
+
+    /// How long each flash lasts (e.g. 500ms, 1s).
+    #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+    interval: Duration,
+
+    /// The color to flash.
+    #[arg(short, long, default_value = "#ffffff")]
+    color: String,
+}
+
+impl Identify {
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
+        let color = Color::parse(&self.color)?;
+
+        let times = match Attention::for_mode(settings.dnd).repeats(self.times) {
+            Some(times) => times,
+            None => {
+                println!("Do not disturb is on, not flashing");
+                return Ok(());
+            }
+        };
+
+        for device in devices.iter() {
+            println!("Flashing {}", device.name);
+            identify::flash(client, device, &color, times, self.interval).await?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Blink a pattern so different alerts can be told apart on one bulb.
+///
+/// Patterns use morse-style notation: `.` is a short blink, `-` a long one,
+/// a space a short pause and `/` a long pause. Named patterns can be defined
+/// as `[[signals]]` in the config. Devices are restored afterwards.
+#[derive(Args)]
+pub struct Signal {
+    /// The pattern to blink, e.g. '..-..'.
+    #[arg(short, long, required_unless_present = "name", conflicts_with = "name")]
+    pattern: Option<String>,
+
+    /// Blink the pattern with this name from the config.
+    #[arg(short, long)]
+    name: Option<String>,
+
+    /// The length of a short blink.
+    #[arg(short, long, default_value = "500ms", value_parser = humantime::parse_duration)]
+    unit: Duration,
+
+    /// How many times to play the pattern.
+    #[arg(short, long, default_value_t = 1)]
+    repeat: usize,
+
+    /// The color to blink.
+    #[arg(short, long, default_value = "#ffffff")]
+    color: String,
+}
+
+impl Signal {
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
+        let raw = match (&self.pattern, &self.name) {
+            (Some(pattern), _) => pattern.clone(),
+            (None, Some(name)) => settings
+                .signals
+                .iter()
+                .flatten()
+                .find(|s| s.name == *name)
+                .map(|s| s.pattern.clone())
+                .ok_or_else(|| anyhow!("No signal named '{}' in config", name))?,
+            (None, None) => unreachable!("clap requires a pattern or a name"),
+        };
+
+        let pattern = Pattern::parse(&raw)?;
+        let color = Color::parse(&self.color)?;
+
+        let repeat = match Attention::for_mode(settings.dnd).repeats(self.repeat) {
+            Some(repeat) => repeat,
+            None => {
+                println!("Do not disturb is on, not signalling");
+                return Ok(());
+            }
+        };
+
+        signal::play(client, devices, &pattern, &color, self.unit, repeat).await
+    }
+}
+
+/// Run a long-running spirit command in the background as a service.
+///
+/// Uses a systemd user unit on Linux and a launchd agent on macOS. Windows
+/// services need the program itself to speak the service control protocol,
+/// which spirit does not, so Windows is not supported.
+#[derive(Subcommand)]
+pub enum ServiceCommands {
+    Install(ServiceInstall),
+    Uninstall(ServiceName),
+    Status(ServiceName),
+}
+
+impl ServiceCommands {
+    /// Only installing needs the api key, so a missing one is only an error
+    /// there.
+    pub fn run(&self, govee_key: Result<&str>) -> Result<()> {
+        match self {
+            Self::Install(cmd) => {
+                Service::new(&cmd.name).install(&cmd.args, govee_key?)?;
+                println!("Installed and started spirit-{}", cmd.name);
+                Ok(())
+            }
+            Self::Uninstall(cmd) => {
+                Service::new(&cmd.name).uninstall()?;
+                println!("Removed spirit-{}", cmd.name);
+                Ok(())
+            }
+            Self::Status(cmd) => Service::new(&cmd.name).status(),
+        }
+    }
+}
+
+/// Scope the device selection, profile and quiet mode to the current shell.
+///
+/// While a session is active, commands run from this shell without --device,
+/// --group or --all use the session's selection instead, and its --profile
+/// and --quiet unless they pass their own.
+#[derive(Subcommand)]
+pub enum SessionCommands {
+    Start(SessionStart),
+    End,
+    Show,
+}
+
+impl SessionCommands {
+    pub fn run(&self, profile: Option<&str>, quiet: bool) -> Result<()> {
+        match self {
+            Self::Start(cmd) => cmd.run(profile, quiet),
+            Self::End => {
+                if Session::end()? {
+                    println!("Session ended");
+                } else {
+                    println!("No active session");
+                }
+                Ok(())
+            }
+            Self::Show => {
+                match Session::load()? {
+                    Some(session) => println!("Session using {}", session.describe()),
+                    None => println!("No active session"),
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Start a session, replacing any active one.
+///
+/// The global --profile and --quiet are kept for the session along with the
+/// selection.
+#[derive(Args)]
+pub struct SessionStart {
+    /// Use all devices regardless of config.
+    #[arg(short, long)]
+    all: bool,
+
+    /// The device name or alias. May be specified multiple times.
+    #[arg(short, long, conflicts_with = "all")]
+    device: Vec<String>,
+
+    /// Use the configured devices in this group. May be specified multiple
+    /// times.
+    #[arg(short = 'G', long, conflicts_with = "all")]
+    group: Vec<String>,
+}
+
+impl SessionStart {
+    pub fn run(&self, profile: Option<&str>, quiet: bool) -> Result<()> {
+        let session = Session {
+            all: self.all,
+            devices: self.device.clone(),
+            groups: self.group.clone(),
+            profile: profile.map(String::from),
+            quiet,
+            shell_started: Session::shell_started(),
+        };
+
+        if !session.selects() && session.profile.is_none() && !session.quiet {
+            bail!("Pass --device, --group, --all, --profile or --quiet to start a session");
+        }
+
+        session.save()?;
+
+        println!(
+            "Session using {} until `spirit session end`",
+            session.describe()
+        );
+
+        Ok(())
+    }
+}
+
+/// Install and start a service.
+///
+/// The service runs from the current directory with the current api key,
+/// e.g. `spirit service install lights -- --group office watch`.
+#[derive(Args)]
+pub struct ServiceInstall {
+    /// A name for the service, used as spirit-<name>.
+    name: String,
+
+    /// The spirit arguments to run.
+    #[arg(last = true, required = true)]
+    args: Vec<String>,
+}
+
+#[derive(Args)]
+pub struct ServiceName {
+    /// The name the service was installed with.
+    name: String,
+}
+
+/// Play back a light show from a json or csv file of keyframes.
+///
+/// Each keyframe has `at` (seconds from the start) and any of `device`,
+/// `color`, `brightness` and `power`. Keyframes without a device apply to
+/// all selected devices. Devices on the LAN transport keep up with fast
+/// sequences far better than the cloud api.
+#[derive(Args)]
+pub struct Play {
+    /// The sequence file. Files ending in .csv are read as csv, anything
+    /// else as json.
+    path: PathBuf,
+
+    /// Start again from the beginning after the last keyframe, until
+    /// interrupted.
+    #[arg(long = "loop")]
+    repeat: bool,
+}
+
+impl Play {
+    pub async fn run(
+        &self,
+        client: &Client,
+        _settings: &Settings,
+        devices: &Devices,
+    ) -> Result<()> {
+        let sequence = Sequence::load(&self.path)?;
+
+        for name in sequence.unknown_devices(devices) {
+            eprintln!("Skipping keyframes for '{}', which is not selected", name);
+        }
+
+        loop {
+            sequence.play(client, devices).await?;
+
+            if !self.repeat {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Receive color frames over udp from an external animation engine.
+///
+/// Each datagram is a json object of device names (or `*`) to colors, e.g.
+/// `{"Desk": "#ff0000"}`. Spirit caps the update rate and fades between
+/// frames. This is only practical for devices on the LAN transport; whole
+/// devices are colored, as segments can't be addressed. The `serve` config's
+/// allowed_ips and tokens apply, with a token passed as a `token` key.
+#[derive(Args)]
+pub struct Stream {
+    /// The address to receive frames on.
+    #[arg(long, default_value = "127.0.0.1:7777")]
+    listen: SocketAddr,
+
+    /// The most updates to send each device per second.
+    #[arg(long, default_value_t = 10)]
+    fps: u32,
+
+    /// How long to fade to each new frame (e.g. 0s, 200ms).
+    #[arg(long, default_value = "100ms", value_parser = humantime::parse_duration)]
+    smoothing: Duration,
+}
+
+impl Stream {
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
+        let devices = capability::color_lights(devices);
+        crate::stream::stream(
+            client,
+            settings,
+            &devices,
+            self.listen,
+            self.fps,
+            self.smoothing,
+        )
+        .await
+    }
+}
+
+/// Show what spirit detects about the CI environment it's running in.
+///
+/// In CI spirit never prompts, and a missing api key or unreachable devices
+/// skip the lights instead of failing, so `spirit check` still runs its
+/// command and exits with its code. Failures are reported as annotations
+/// where the CI system supports them. Setting SPIRIT_DISABLE=1 skips device
+/// operations everywhere.
+#[derive(Args)]
+pub struct CiEnv {}
+
+impl CiEnv {
+    pub fn run(&self, ci: &ci::Environment, has_key: bool) -> Result<()> {
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+
+        println!(
+            "provider: {}",
+            ci.provider
+                .map(|p| p.to_string())
+                .unwrap_or_else(|| "none".to_string())
+        );
+        println!("interactive: {}", yes_no(ci.interactive()));
+        println!("api key: {}", if has_key { "set" } else { "missing" });
+
+        let devices = if ci.disabled {
+            "skipped (SPIRIT_DISABLE is set)"
+        } else if !has_key && ci.is_ci() {
+            "skipped (no GOVEE_KEY)"
+        } else if ci.is_ci() {
+            "used if reachable"
+        } else {
+            "used"
+        };
+        println!("devices: {}", devices);
+
+        Ok(())
+    }
+}
+
+/// Walk through devices that aren't in the config yet and add them.
+///
+/// Each unconfigured device is flashed so it can be found, then you are
+/// prompted for an alias and groups. The new entries are added to the
+/// config file.
+#[derive(Args)]
+pub struct Adopt {
+    /// The number of flashes per device.
+    #[arg(long, default_value_t = 3)]
+    times: usize,
+}
+
+impl Adopt {
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
+        let device_settings = settings.device_settings();
+        let color = Color::parse("#ffffff")?;
+
+        let unconfigured: Vec<_> = devices
+            .iter()
+            .filter(|d| device_settings.get(&d.name).is_none())
+            .filter(|d| device_settings.find_by_id(&d.device).is_none())
+            .collect();
+
+        if unconfigured.is_empty() {
+            println!("Every device is already configured");
+            return Ok(());
+        }
+
+        let times = Attention::for_mode(settings.dnd).repeats(self.times);
+        if times.is_none() {
+            println!("Do not disturb is on, not flashing");
+        }
+
+        let mut adopted = Vec::new();
+
+        for device in unconfigured {
+            println!("\n{} ({}, {})", device.name, device.model, device.device);
+            if let Some(times) = times {
+                identify::flash(client, device, &color, times, Duration::from_millis(750)).await?;
+            }
+
+            let alias = prompt("  alias (blank for none, 's' to skip, 'q' to stop): ")?;
+            match alias.as_str() {
+                "s" => continue,
+                "q" => break,
+                _ => {}
+            }
+
+            let groups = prompt("  groups (comma separated, blank for none): ")?;
+
+            adopted.push(NewDevice {
+                name: device.name.clone(),
+                id: device.device.clone(),
+                alias: Some(alias).filter(|a| !a.is_empty()),
+                groups: groups
+                    .split(',')
+                    .map(|g| g.trim().to_string())
+                    .filter(|g| !g.is_empty())
+                    .collect(),
+                ..Default::default()
+            });
+        }
+
+        if adopted.is_empty() {
+            return Ok(());
+        }
+
+        let path = Settings::writable_path()?;
+        append_devices(&path, &adopted)?;
+
+        println!("\nAdded new devices to {}", path.display());
+
+        Ok(())
+    }
+}
+
+/// The comma separated entries of an environment variable, if it's set.
+fn env_list(name: &str) -> Vec<String> {
+    std::env::var(name)
+        .map(|v| {
+            v.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn prompt(question: &str) -> Result<String> {
+    print!("{}", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(answer.trim().to_string())
+}
+
+/// Poll a set of devices and run hooks when they change.
+///
+/// Devices can set `on_power_on` and `on_power_off` shell commands in the
+/// config, turning lights into switches for arbitrary actions, and
+/// `[[auto_off]]` rules turn off lights left on too long. Runs until
+/// interrupted.
+#[derive(Args)]
+pub struct Watch {
+    /// How often to poll (e.g. 30s, 1m).
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    interval: Duration,
+
+    /// Run this shell command on every power or connectivity change.
+    ///
+    /// `{device}`, `{old}` and `{new}` are replaced, e.g.
+    /// 'script.sh {device} {old} {new}'.
+    #[arg(long)]
+    on_change: Option<String>,
+}
+
+impl Watch {
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
+        crate::watch::watch(
+            client,
+            settings,
+            devices,
+            self.interval,
+            self.on_change.as_deref(),
+        )
+        .await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum KeylightMode {
+    On,
+    Off,
+    /// Turn the key light on while a webcam is in use (Linux only).
+    Auto,
+}
+
+/// Use a light as a video call key light.
+///
+/// The color temperature, brightness and device come from the `keylight`
+/// section of the config, defaulting to 5000K at 80% on the selected
+/// devices.
+#[derive(Args)]
+pub struct Keylight {
+    #[arg(value_enum)]
+    mode: KeylightMode,
+
+    /// How often auto mode checks the webcam.
+    #[arg(long, default_value = "2s", value_parser = humantime::parse_duration)]
+    interval: Duration,
+}
+
+impl Keylight {
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
+        let preset = settings.keylight.clone().unwrap_or(KeylightSetting {
+            device: None,
+            kelvin: 5000,
+            brightness: 80,
+        });
+
+        let mut targets = capability::white_lights(devices);
+        if let Some(ref name) = preset.device {
+            targets.devices.retain(|d| d.name == *name);
+
+            if targets.devices.is_empty() {
+                bail!("Key light device '{}' is not selected", name);
+            }
+        }
+
+        match self.mode {
+            KeylightMode::On => Self::set(client, &targets, &preset, true).await,
+            KeylightMode::Off => Self::set(client, &targets, &preset, false).await,
+            KeylightMode::Auto => {
+                if !cfg!(target_os = "linux") {
+                    bail!("Webcam detection is only supported on Linux");
+                }
+
+                let mut lit = None;
+
+                loop {
+                    let in_use = webcam::in_use();
+
+                    if lit != Some(in_use) {
+                        events::record(
+                            "keylight",
+                            if in_use {
+                                "webcam in use"
+                            } else {
+                                "webcam idle"
+                            },
+                        );
+                        Self::set(client, &targets, &preset, in_use).await?;
+                        lit = Some(in_use);
+                    }
+
+                    tokio::time::sleep(self.interval).await;
+                }
+            }
+        }
+    }
+
+    async fn set(
+        client: &Client,
+        devices: &Devices,
+        preset: &KeylightSetting,
+        on: bool,
+    ) -> Result<()> {
+        for device in devices.iter() {
+            if on {
+                client.color_temperature(device, preset.kelvin).await?;
+                client.brightness(device, preset.brightness).await?;
+            } else {
+                client.turn(device, PowerState::Off).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Slowly wander each device through the colors of a palette.
+///
+/// Every device moves on its own schedule: each step waits the interval
+/// plus or minus a random amount up to the jitter, then picks a different
+/// color from the palette. Palettes come from `[[palettes]]` in the config
+/// or are one of the built in forest, ocean, sunset or ember.
+#[derive(Args)]
+pub struct AmbientDrift {
+    /// The palette to draw colors from.
+    #[arg(short, long, default_value = "forest")]
+    palette: String,
+
+    /// The average time between color changes.
+    #[arg(short, long, default_value = "5m", value_parser = humantime::parse_duration)]
+    interval: Duration,
+
+    /// The most each step may be moved earlier or later.
+    #[arg(short, long, default_value = "30s", value_parser = humantime::parse_duration)]
+    jitter: Duration,
+}
+
+impl AmbientDrift {
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
+        let palette = Palette::find(settings.palettes.as_deref(), &self.palette)?;
+        let devices = &capability::color_lights(devices);
+
+        futures::future::join_all(
+            devices
+                .iter()
+                .map(|device| self.drift(client, device, &palette)),
+        )
+        .await;
+
+        Ok(())
+    }
+
+    async fn drift(&self, client: &Client, device: &Device, palette: &Palette) {
+        let mut current = None;
+
+        loop {
+            let choices: Vec<&Rgb> = palette
+                .colors
+                .iter()
+                .filter(|c| Some(**c) != current || palette.colors.len() == 1)
+                .collect();
+
+            let (next, wait) = {
+                let mut rng = rand::thread_rng();
+                let next = **choices.choose(&mut rng).expect("palettes are never empty");
+                let jitter = self.jitter.as_secs_f64() * rng.gen_range(-1.0..=1.0);
+                let wait = (self.interval.as_secs_f64() + jitter).max(1.0);
+                (next, Duration::from_secs_f64(wait))
+            };
+
+            let result = match next.to_color() {
+                Ok(color) => client.color(device, color).await,
+                Err(e) => Err(e),
+            };
+
+            match result {
+                Ok(_) => current = Some(next),
+                Err(e) => {
+                    events::record("error", format!("drift {}: {:#}", device.name, e));
+                    eprintln!("Could not set {}: {:#}", device.name, e);
+                }
+            }
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Look back at device states sampled by `spirit watch`.
+#[derive(Subcommand)]
+pub enum HistoryCommands {
+    Device(HistoryDevice),
+}
+
+impl HistoryCommands {
+    pub fn run(&self, settings: &Settings) -> Result<()> {
+        match self {
+            Self::Device(cmd) => cmd.run(settings),
+        }
+    }
+}
+
+/// Show when a device was on or off and what colors it held.
+///
+/// Consecutive samples in the same state are shown as one span, followed by
+/// the total time the device was on.
+#[derive(Args)]
+pub struct HistoryDevice {
+    /// The device name or alias.
+    name: String,
+
+    /// How far back to look (e.g. 24h, 7d).
+    #[arg(long, default_value = "24h", value_parser = humantime::parse_duration)]
+    since: Duration,
+}
+
+impl HistoryDevice {
+    pub fn run(&self, settings: &Settings) -> Result<()> {
+        let device_settings = settings.device_settings();
+        let name = device_settings
+            .0
+            .values()
+            .find(|s| s.alias.as_deref() == Some(self.name.as_str()))
+            .map(|s| s.name.as_str())
+            .unwrap_or(self.name.as_str());
+
+        let now = cache::now();
+        let samples =
+            History::open(now)?.samples(name, now.saturating_sub(self.since.as_secs()))?;
+
+        if samples.is_empty() {
+            println!(
+                "No history for {} in the last {} (it is recorded by `spirit watch`)",
+                name,
+                humantime::format_duration(self.since)
+            );
+            return Ok(());
+        }
+
+        let names = ColorNames::new(settings);
+        let time = |at: u64| {
+            Local
+                .timestamp_opt(at as i64, 0)
+                .single()
+                .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default()
+        };
+
+        let spans = history::spans(&samples);
+        for span in spans.iter() {
+            let state = match (span.snapshot.online, span.snapshot.power) {
+                (Some(false), _) => "offline".to_string(),
+                (_, Some(true)) => {
+                    let mut state = "on".to_string();
+                    if let Some(brightness) = span.snapshot.brightness {
+                        state.push_str(&format!(" {}%", brightness));
+                    }
+                    if let Some(ref color) = span.snapshot.color {
+                        state.push_str(&format!(" {}", names.describe(color)));
+                    }
+                    state
+                }
+                (_, Some(false)) => "off".to_string(),
+                _ => "unknown".to_string(),
+            };
+
+            println!("{} - {}  {}", time(span.from), time(span.to), state);
+        }
+
+        println!(
+            "On for {} of the last {}",
+            humantime::format_duration(history::on_time(&spans)),
+            humantime::format_duration(self.since)
+        );
+
+        Ok(())
+    }
+}
+
+/// Summaries built from the history `spirit watch` records.
+#[derive(Subcommand)]
+pub enum ReportCommands {
+    Energy(ReportEnergy),
+}
+
+impl ReportCommands {
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
+        match self {
+            Self::Energy(cmd) => cmd.run(client, settings, devices).await,
+        }
+    }
+}
+
+/// Estimate how long each device was on and roughly how much energy it used.
+///
+/// Energy needs the wattage of each model in the `wattage` config table and
+/// assumes draw scales with brightness, so treat it as a rough picture.
+#[derive(Args)]
+pub struct ReportEnergy {
+    /// How far back to look (e.g. 7d, 30d).
+    #[arg(long, default_value = "7d", value_parser = humantime::parse_duration)]
+    since: Duration,
+
+    /// The price of a kWh, to also estimate cost.
+    #[arg(long)]
+    price: Option<f64>,
+}
+
+impl ReportEnergy {
+    pub async fn run(
+        &self,
+        _client: &Client,
+        settings: &Settings,
+        devices: &Devices,
+    ) -> Result<()> {
+        let now = cache::now();
+        let since = now.saturating_sub(self.since.as_secs());
+        let history = History::open(now)?;
+        let wattage = settings.wattage.clone().unwrap_or_default();
+
+        println!("{:<24} {:>16} {:>8} {:>8}", "device", "on", "kWh", "cost");
+
+        let mut total = 0.0;
+        let mut unknown = Vec::new();
+
+        for device in devices.iter() {
+            let spans = history::spans(&history.samples(&device.name, since)?);
+            let on = Duration::from_secs(history::on_time(&spans).as_secs() / 60 * 60);
+
+            let kwh = wattage
+                .get(&device.model)
+                .map(|watts| history::energy(&spans, *watts) / 1000.0);
+            if kwh.is_none() && !unknown.contains(&device.model) {
+                unknown.push(device.model.clone());
+            }
+            total += kwh.unwrap_or_default();
+
+            let cost = match (kwh, self.price) {
+                (Some(kwh), Some(price)) => format!("{:.2}", kwh * price),
+                _ => "-".to_string(),
+            };
+
+            println!(
+                "{:<24} {:>16} {:>8} {:>8}",
+                device.name,
+                humantime::format_duration(on).to_string(),
+                kwh.map(|k| format!("{:.2}", k))
+                    .unwrap_or_else(|| "?".to_string()),
+                cost
+            );
+        }
+
+        let cost = match self.price {
+            Some(price) => format!("{:.2}", total * price),
+            None => "-".to_string(),
+        };
+        println!("{:<24} {:>16} {:>8.2} {:>8}", "total", "", total, cost);
+
+        if !unknown.is_empty() {
+            eprintln!(
+                "No wattage configured for {}, add them to the [wattage] table",
+                unknown.join(", ")
+            );
+        }
+
+        Ok(())
+    }
+}
+
+/// Work with the colors spirit uses.
+#[derive(Subcommand)]
+pub enum PaletteCommands {
+    Lint(PaletteLint),
+}
+
+impl PaletteCommands {
+    pub fn run(&self, settings: &Settings) -> Result<()> {
+        match self {
+            Self::Lint(cmd) => cmd.run(settings),
+        }
+    }
+}
+
+/// Warn about success and fail colors that are hard to tell apart.
+///
+/// The global colors and each device's effective colors are compared by
+/// Delta-E, both as configured and as seen with protanopia, deuteranopia
+/// and tritanopia.
+#[derive(Args)]
+pub struct PaletteLint {
+    /// The smallest acceptable Delta-E between two colors.
+    #[arg(long, default_value_t = 30.0)]
+    min_delta: f64,
+}
+
+impl PaletteLint {
+    /// Colors from the Okabe-Ito palette that stay distinct under all three
+    /// deficiencies.
+    const SUGGESTED_SUCCESS: &'static str = "#009e73";
+    const SUGGESTED_FAIL: &'static str = "#d55e00";
+
+    pub fn run(&self, settings: &Settings) -> Result<()> {
+        let mut pairs = vec![(
+            "default".to_string(),
+            settings.success.clone(),
+            settings.fail.clone(),
+        )];
+
+        if let Some(ref devices) = settings.devices {
+            for device in devices {
+                if device.success.is_some() || device.fail.is_some() {
+                    pairs.push((
+                        device.name.clone(),
+                        device
+                            .success
+                            .clone()
+                            .unwrap_or_else(|| settings.success.clone()),
+                        device.fail.clone().unwrap_or_else(|| settings.fail.clone()),
+                    ));
+                }
+            }
+        }
+
+        let mut issues = 0;
+
+        for (name, success, fail) in pairs.iter() {
+            let success = Rgb::parse(success)
+                .with_context(|| format!("Invalid success color for {}", name))?;
+            let fail =
+                Rgb::parse(fail).with_context(|| format!("Invalid fail color for {}", name))?;
+
+            let delta = success.delta_e(fail);
+            if delta < self.min_delta {
+                println!(
+                    "{}: success {} and fail {} are too similar (Delta-E {:.1})",
+                    name, success, fail, delta
+                );
+                issues += 1;
+                continue;
+            }
+
+            for deficiency in Deficiency::ALL {
+                let delta = success
+                    .simulate(deficiency)
+                    .delta_e(fail.simulate(deficiency));
+
+                if delta < self.min_delta {
+                    println!(
+                        "{}: success {} and fail {} are hard to tell apart with {} (Delta-E {:.1})",
+                        name, success, fail, deficiency, delta
+                    );
+                    issues += 1;
+                }
+            }
+        }
+
+        if issues == 0 {
+            println!("No issues found");
+            return Ok(());
+        }
+
+        println!(
+            "\nConsider success = \"{}\" and fail = \"{}\", which stay distinct under common color-vision deficiencies",
+            Self::SUGGESTED_SUCCESS,
+            Self::SUGGESTED_FAIL
+        );
+
+        bail!("Found {} palette issue(s)", issues)
+    }
+}
+
+/// Bind global keyboard shortcuts to actions on a set of devices.
+///
+/// Bindings are read from the `hotkeys` entries in the config. Runs until
+/// interrupted.
+#[cfg(feature = "hotkeys")]
+#[derive(Args)]
+pub struct Hotkeys;
+
+#[cfg(feature = "hotkeys")]
+impl Hotkeys {
+    pub async fn run(&self, client: &Client, settings: &Settings, devices: &Devices) -> Result<()> {
+        let bindings = settings.hotkeys.as_deref().unwrap_or_default();
+        crate::hotkeys::listen(client, devices, bindings).await
+    }
+}
+
+/// Acknowledge sticky alerts on a set of devices.
+///
+/// By default each device is restored to the state it was in before the
+/// alert was raised.
+#[derive(Args)]
+pub struct Ack {
+    /// Clear the alert without restoring the previous state.
+    #[arg(long)]
+    no_restore: bool,
+}
+
+impl Ack {
+    pub async fn run(
+        &self,
+        client: &Client,
+        _settings: &Settings,
+        devices: &Devices,
+    ) -> Result<()> {
+        let mut alerts = Alerts::load()?;
+
+        for device in devices.iter() {
+            if let Some(previous) = alerts.clear(&device.name) {
+                if !self.no_restore {
+                    previous.apply(client, device).await?;
+                }
             }
-            .unwrap();
-            client.color(device, color).await?;
         }
 
-        std::process::exit(res.code().expect("could not get status code"));
+        alerts.save()
     }
 }
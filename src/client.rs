@@ -0,0 +1,909 @@
+use anyhow::{anyhow, bail, Result};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::net::IpAddr;
+use std::ops::Deref;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, Instant};
+
+use futures::{stream, StreamExt};
+use govee_rs::{
+    models::{Color, Device, Devices, PowerState},
+    GoveeClient,
+};
+use tokio::{
+    sync::{watch, Semaphore, SemaphorePermit},
+    task::JoinSet,
+};
+
+use crate::{
+    capability,
+    color::Rgb,
+    correction::Correction,
+    health::{Health, Outcome},
+    lan::LanClient,
+    settings::{DeviceSetting, Settings, Transport},
+    state::DeviceSnapshot,
+    throttle::Throttle,
+};
+
+/// How many times a rate limited cloud request is retried.
+const RATE_LIMIT_RETRIES: usize = 3;
+
+/// How many device states are fetched at once by `prefetch`.
+const PREFETCH_CONCURRENCY: usize = 4;
+
+/// How often recorded outcomes are written to the health file. Anything left
+/// over is written when the client goes away.
+const HEALTH_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Where commands for a particular device should be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Route {
+    Cloud,
+    Lan(IpAddr),
+    /// Prefer the LAN. Only state reads fall back to the cloud when the LAN
+    /// request fails: control messages get no reply, so a failed one can't
+    /// be told apart from one that worked.
+    Auto(IpAddr),
+}
+
+/// Dispatches device operations over the cloud or LAN api according to each
+/// device's configured transport.
+///
+/// One client lives for the whole invocation, so it also remembers device
+/// states it has fetched. That way the alert check, a sticky check and the
+/// command itself share a single `state` call per device.
+///
+/// Clones are cheap and share everything, so operations can run as spawned
+/// tasks (see `each`) while still being throttled, recorded and cancelled
+/// together.
+#[derive(Clone)]
+pub struct Client {
+    shared: Arc<Shared>,
+    max_duration: Option<Duration>,
+    quiet: bool,
+    /// Set on the handle from `restoring`, which cancels can't stop.
+    restoring: bool,
+}
+
+/// What happened to one device, for the summary after bulk commands.
+#[derive(Debug, Default)]
+struct DeviceResult {
+    /// The most recent change sent.
+    action: String,
+    latency: Duration,
+    ops: usize,
+    failed: usize,
+    /// The most recent failure.
+    error: Option<String>,
+}
+
+/// The state shared by every clone of a `Client`.
+pub struct Shared {
+    cloud: GoveeClient,
+    lan: LanClient,
+    routes: HashMap<String, Route>,
+    throttle: Throttle,
+    limits: Limits,
+    health: Mutex<PendingHealth>,
+    deadline: Mutex<Option<tokio::time::Instant>>,
+    expired: AtomicBool,
+    interrupted: AtomicBool,
+    stop: watch::Sender<bool>,
+    completed: Mutex<HashSet<String>>,
+    results: Mutex<HashMap<String, DeviceResult>>,
+    states: Mutex<HashMap<String, DeviceSnapshot>>,
+    device_corrections: HashMap<String, Correction>,
+    model_corrections: HashMap<String, Correction>,
+    brightness_limits: BrightnessLimits,
+}
+
+/// Per-device brightness floors and ceilings, from the config.
+///
+/// Every brightness change a command or effect makes (scenes, sequences,
+/// restores, fades) is sent through `Client::brightness`, which holds it to
+/// these, so nothing can get around them.
+#[derive(Default)]
+struct BrightnessLimits(HashMap<String, (u8, u8)>);
+
+impl BrightnessLimits {
+    fn from_settings<'a>(settings: impl IntoIterator<Item = &'a DeviceSetting>) -> Self {
+        let mut ranges = HashMap::new();
+        for setting in settings {
+            match setting.brightness_range() {
+                Ok(Some(range)) => {
+                    ranges.insert(setting.name.clone(), range);
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!("Ignoring brightness limits for {}: {:#}", setting.name, e),
+            }
+        }
+        Self(ranges)
+    }
+
+    /// `value`, held within the device called `name`'s limits.
+    fn apply(&self, name: &str, value: u8) -> u8 {
+        match self.0.get(name) {
+            Some(&(min, max)) => value.clamp(min, max),
+            None => value,
+        }
+    }
+}
+
+/// Outcomes waiting to be written to the health file.
+struct PendingHealth {
+    outcomes: Vec<(String, Outcome)>,
+    flushed: Instant,
+}
+
+/// Caps on concurrent device operations, from the `concurrency` config.
+#[derive(Default)]
+struct Limits {
+    cloud: Option<Semaphore>,
+    lan: Option<Semaphore>,
+    /// By group name. Kept sorted so they're always taken in the same order,
+    /// which keeps operations waiting on several from deadlocking.
+    groups: BTreeMap<String, Semaphore>,
+    /// Each configured device's groups.
+    device_groups: HashMap<String, Vec<String>>,
+}
+
+impl Limits {
+    fn from_settings(settings: &Settings) -> Self {
+        let concurrency = settings.concurrency.clone().unwrap_or_default();
+
+        let limit = |what: &str, max: usize| {
+            if max == 0 {
+                eprintln!("Ignoring a concurrency limit of 0 for {}", what);
+                None
+            } else {
+                Some(Semaphore::new(max))
+            }
+        };
+
+        let mut groups = BTreeMap::new();
+        for (group, max) in concurrency.groups.iter().flatten() {
+            if let Some(semaphore) = limit(&format!("group {}", group), *max) {
+                groups.insert(group.clone(), semaphore);
+            }
+        }
+
+        Self {
+            cloud: concurrency.cloud.and_then(|max| limit("cloud", max)),
+            lan: concurrency.lan.and_then(|max| limit("lan", max)),
+            groups,
+            device_groups: settings
+                .devices
+                .iter()
+                .flatten()
+                .map(|d| (d.name.clone(), d.groups.clone().unwrap_or_default()))
+                .collect(),
+        }
+    }
+}
+
+impl Deref for Client {
+    type Target = Shared;
+
+    fn deref(&self) -> &Shared {
+        &self.shared
+    }
+}
+
+impl Client {
+    pub fn new(cloud: GoveeClient, settings: &Settings) -> Self {
+        let mut routes = HashMap::new();
+
+        for setting in settings.device_settings().0.values() {
+            let route = match (setting.transport.unwrap_or_default(), setting.ip) {
+                (Transport::Cloud, _) | (Transport::Auto, None) => Route::Cloud,
+                (Transport::Lan, Some(ip)) => Route::Lan(ip),
+                (Transport::Auto, Some(ip)) => Route::Auto(ip),
+                (Transport::Lan, None) => {
+                    eprintln!(
+                        "Device {} uses the lan transport but has no ip, using the cloud",
+                        setting.name
+                    );
+                    Route::Cloud
+                }
+            };
+            routes.insert(setting.name.clone(), route);
+        }
+
+        let mut device_corrections = HashMap::new();
+        for setting in settings.device_settings().0.values() {
+            if let Some(ref correction) = setting.correction {
+                match Correction::from_setting(correction) {
+                    Ok(correction) => {
+                        device_corrections.insert(setting.name.clone(), correction);
+                    }
+                    Err(e) => eprintln!("Ignoring color correction for {}: {:#}", setting.name, e),
+                }
+            }
+        }
+
+        let mut model_corrections = HashMap::new();
+        for (model, correction) in settings.corrections.iter().flatten() {
+            match Correction::from_setting(correction) {
+                Ok(correction) => {
+                    model_corrections.insert(model.clone(), correction);
+                }
+                Err(e) => eprintln!("Ignoring color correction for {}: {:#}", model, e),
+            }
+        }
+
+        let (stop, _) = watch::channel(false);
+
+        Self {
+            shared: Arc::new(Shared {
+                cloud,
+                lan: LanClient::default(),
+                routes,
+                throttle: Throttle::default(),
+                limits: Limits::from_settings(settings),
+                health: Mutex::new(PendingHealth {
+                    outcomes: Vec::new(),
+                    flushed: Instant::now(),
+                }),
+                deadline: Mutex::new(None),
+                expired: AtomicBool::new(false),
+                interrupted: AtomicBool::new(false),
+                stop,
+                completed: Mutex::new(HashSet::new()),
+                results: Mutex::new(HashMap::new()),
+                states: Mutex::new(HashMap::new()),
+                device_corrections,
+                model_corrections,
+                brightness_limits: BrightnessLimits::from_settings(
+                    settings.device_settings().0.values(),
+                ),
+            }),
+            max_duration: None,
+            quiet: false,
+            restoring: false,
+        }
+    }
+
+    /// Cancel device operations still in flight `max_duration` after the
+    /// first one starts.
+    pub fn with_max_duration(mut self, max_duration: Option<Duration>) -> Self {
+        self.max_duration = max_duration;
+        self
+    }
+
+    /// Skip the per-device result summary.
+    pub fn with_quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// A handle for putting devices back the way they were, whose operations
+    /// still run after a cancel or once the max duration has passed.
+    ///
+    /// Cancelling stops new work, but a command that changed a device
+    /// temporarily should still get to undo it. Only use this for that.
+    pub fn restoring(&self) -> Self {
+        Self {
+            restoring: true,
+            ..self.clone()
+        }
+    }
+
+    /// Stop every device operation in flight, on this client and all its
+    /// clones (except `restoring` ones), and refuse to start new ones.
+    pub fn cancel(&self) {
+        self.interrupted.store(true, Ordering::SeqCst);
+        self.stop.send_replace(true);
+    }
+
+    /// Whether the max duration or a cancel cut things short.
+    fn stopped(&self) -> bool {
+        self.expired.load(Ordering::SeqCst) || self.interrupted.load(Ordering::SeqCst)
+    }
+
+    /// Run a device operation, stopping it if the client is cancelled or the
+    /// max duration has passed.
+    async fn bounded<T>(&self, op: impl Future<Output = Result<T>>) -> Result<T> {
+        if self.restoring {
+            return op.await;
+        }
+
+        let mut cancelled = self.stop.subscribe();
+        if *cancelled.borrow() {
+            bail!("Cancelled");
+        }
+
+        let deadline = self.max_duration.map(|max_duration| {
+            *self
+                .deadline
+                .lock()
+                .expect("deadline lock poisoned")
+                .get_or_insert_with(|| tokio::time::Instant::now() + max_duration)
+        });
+
+        let expired = async {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => futures::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            res = op => res,
+            _ = expired => {
+                self.expired.store(true, Ordering::SeqCst);
+                bail!(
+                    "Max duration of {} exceeded",
+                    humantime::format_duration(self.max_duration.unwrap_or_default())
+                )
+            }
+            _ = cancelled.changed() => bail!("Cancelled"),
+        }
+    }
+
+    /// Run `op` for each of `devices` as a group of tasks, returning each
+    /// device's result in order.
+    ///
+    /// The tasks never outlive the call: dropping the returned future aborts
+    /// any still running, and cancelling the client makes the operations
+    /// inside them stop.
+    pub async fn each<'a, F, Fut, T>(
+        &self,
+        devices: impl IntoIterator<Item = &'a Device>,
+        op: F,
+    ) -> Vec<(&'a Device, Result<T>)>
+    where
+        F: Fn(Client, Device) -> Fut,
+        Fut: Future<Output = Result<T>> + Send + 'static,
+        T: Send + 'static,
+    {
+        let devices: Vec<&Device> = devices.into_iter().collect();
+        let mut tasks = JoinSet::new();
+
+        for (i, device) in devices.iter().enumerate() {
+            let op = op(self.clone(), (*device).clone());
+            tasks.spawn(async move { (i, op.await) });
+        }
+
+        let mut results: Vec<Option<Result<T>>> = devices.iter().map(|_| None).collect();
+        while let Some(joined) = tasks.join_next().await {
+            match joined {
+                Ok((i, res)) => results[i] = Some(res),
+                Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+                Err(_) => {}
+            }
+        }
+
+        devices
+            .into_iter()
+            .zip(results)
+            .map(|(device, res)| (device, res.unwrap_or_else(|| Err(anyhow!("Cancelled")))))
+            .collect()
+    }
+
+    /// If the max duration or a cancel cut things short, describe which of
+    /// `devices` were completed and which were skipped.
+    pub fn stop_summary(&self, devices: &Devices) -> Option<String> {
+        if !self.stopped() {
+            return None;
+        }
+
+        let reason = if self.interrupted.load(Ordering::SeqCst) {
+            "Interrupted"
+        } else {
+            "Stopped after the max duration"
+        };
+
+        let completed = self.completed.lock().expect("completed lock poisoned");
+        let (done, skipped): (Vec<&str>, Vec<&str>) = devices
+            .iter()
+            .map(|d| d.name.as_str())
+            .partition(|name| completed.contains(*name));
+
+        Some(format!(
+            "{}: completed {} ({}), skipped {} ({})",
+            reason,
+            done.len(),
+            done.join(", "),
+            skipped.len(),
+            skipped.join(", ")
+        ))
+    }
+
+    fn route(&self, device: &Device) -> Route {
+        self.routes
+            .get(&device.name)
+            .copied()
+            .unwrap_or(Route::Cloud)
+    }
+
+    /// Wait for a slot under each concurrency limit `device` falls under:
+    /// its transport's, then its groups' in name order. The slots are freed
+    /// when the returned permits are dropped.
+    ///
+    /// Every device operation takes its slots first, so the limits hold
+    /// however the operations came to overlap.
+    async fn acquire(&self, device: &Device) -> Vec<SemaphorePermit<'_>> {
+        let transport = match self.route(device) {
+            Route::Cloud => self.limits.cloud.as_ref(),
+            Route::Lan(_) | Route::Auto(_) => self.limits.lan.as_ref(),
+        };
+
+        let own = self.limits.device_groups.get(&device.name);
+        let groups = self
+            .limits
+            .groups
+            .iter()
+            .filter(|(group, _)| own.map(|own| own.contains(group)).unwrap_or(false))
+            .map(|(_, limit)| limit);
+
+        let mut permits = Vec::new();
+        for limit in transport.into_iter().chain(groups) {
+            permits.push(limit.acquire().await.expect("concurrency limit closed"));
+        }
+        permits
+    }
+
+    /// Run a cloud request through the throttle, retrying it if the api
+    /// responds with a rate limit error.
+    async fn throttled<T, E, F, Fut>(&self, request: F) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = std::result::Result<T, E>>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let mut attempt = 0;
+
+        loop {
+            self.throttle.acquire().await;
+
+            let start = Instant::now();
+            match request().await {
+                Ok(res) => {
+                    self.throttle.success(start.elapsed());
+                    return Ok(res);
+                }
+                Err(e) if is_rate_limit(&e) && attempt < RATE_LIMIT_RETRIES => {
+                    let wait = self.throttle.rate_limited();
+                    eprintln!("Rate limited by the govee api, retrying in {:?}", wait);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// The list of devices known to the cloud api.
+    pub async fn devices(&self) -> Result<Devices> {
+        self.throttled(|| self.cloud.devices()).await
+    }
+
+    pub async fn turn(&self, device: &Device, state: PowerState) -> Result<()> {
+        let _permits = self.acquire(device).await;
+
+        let started = Instant::now();
+        let res: Result<()> = self
+            .bounded(async {
+                match self.route(device) {
+                    Route::Cloud => {
+                        self.throttled(|| self.cloud.turn(device, state)).await?;
+                    }
+                    Route::Lan(ip) | Route::Auto(ip) => {
+                        self.lan.turn(ip, state == PowerState::On).await?;
+                    }
+                }
+                Ok(())
+            })
+            .await;
+
+        self.forget(device);
+        let action = match state {
+            PowerState::On => "on",
+            _ => "off",
+        };
+        self.note(device, action.to_string(), started.elapsed(), &res);
+        self.record(device, &res);
+        res
+    }
+
+    /// Set `device` to `color`, after applying any configured correction.
+    pub async fn color(&self, device: &Device, color: Color) -> Result<()> {
+        let color = self.correct(device, color)?;
+        self.color_raw(device, color).await
+    }
+
+    /// Set `device` to exactly `color`, skipping correction.
+    ///
+    /// For colors read back from a device (restoring a snapshot or a captured
+    /// scene), which were already corrected when they were first sent.
+    pub async fn color_raw(&self, device: &Device, color: Color) -> Result<()> {
+        if !capability::supports(device, "color") {
+            bail!("{} does not support color", device.name);
+        }
+
+        let _permits = self.acquire(device).await;
+
+        let started = Instant::now();
+        let res: Result<()> = self
+            .bounded(async {
+                match self.route(device) {
+                    Route::Cloud => {
+                        self.throttled(|| self.cloud.color(device, color.clone()))
+                            .await?;
+                    }
+                    Route::Lan(ip) | Route::Auto(ip) => {
+                        self.lan.color(ip, color.r, color.g, color.b).await?;
+                    }
+                }
+                Ok(())
+            })
+            .await;
+
+        self.forget(device);
+        let action = format!("color #{:02x}{:02x}{:02x}", color.r, color.g, color.b);
+        self.note(device, action, started.elapsed(), &res);
+        self.record(device, &res);
+        res
+    }
+
+    /// Set `device` to a white of `kelvin`.
+    ///
+    /// Over the LAN, devices that support color temperature are sent it
+    /// directly, so ones with white LEDs use them. Anything else gets the
+    /// nearest RGB color.
+    pub async fn color_temperature(&self, device: &Device, kelvin: u32) -> Result<()> {
+        let ip = match self.route(device) {
+            Route::Lan(ip) | Route::Auto(ip) if capability::has_color_temperature(device) => ip,
+            _ => {
+                return self
+                    .color(device, Rgb::from_kelvin(kelvin).to_color()?)
+                    .await
+            }
+        };
+
+        let _permits = self.acquire(device).await;
+
+        let started = Instant::now();
+        let res = self
+            .bounded(async { self.lan.color_temperature(ip, kelvin).await })
+            .await;
+
+        self.forget(device);
+        self.note(device, format!("{}K", kelvin), started.elapsed(), &res);
+        self.record(device, &res);
+        res
+    }
+
+    pub async fn brightness(&self, device: &Device, value: u8) -> Result<()> {
+        if !capability::supports(device, "brightness") {
+            bail!("{} does not support brightness", device.name);
+        }
+
+        let value = self.brightness_limits.apply(&device.name, value);
+
+        let _permits = self.acquire(device).await;
+
+        let started = Instant::now();
+        let res: Result<()> = self
+            .bounded(async {
+                match self.route(device) {
+                    Route::Cloud => {
+                        self.throttled(|| self.cloud.brightness(device, value))
+                            .await?;
+                    }
+                    Route::Lan(ip) | Route::Auto(ip) => {
+                        self.lan.brightness(ip, value).await?;
+                    }
+                }
+                Ok(())
+            })
+            .await;
+
+        self.forget(device);
+        self.note(
+            device,
+            format!("brightness {}", value),
+            started.elapsed(),
+            &res,
+        );
+        self.record(device, &res);
+        res
+    }
+
+    /// Fetch the states of `devices` concurrently, so later `snapshot` calls
+    /// don't have to wait on them one at a time.
+    ///
+    /// Failures aren't reported here, the device is just fetched again (and
+    /// the error surfaced) when something asks for it.
+    ///
+    /// Only cloud-routed devices are prefetched. LAN status requests take
+    /// turns on a single response port, so fanning them out gains nothing.
+    pub async fn prefetch(&self, devices: &Devices) {
+        let cloud = devices
+            .iter()
+            .filter(|d| d.retrievable && self.route(d) == Route::Cloud);
+
+        stream::iter(cloud)
+            .for_each_concurrent(PREFETCH_CONCURRENCY, |device| async move {
+                let _ = self.snapshot(device).await;
+            })
+            .await;
+    }
+
+    /// The state of `device`, fetched at most once per invocation unless
+    /// spirit changes the device in the meantime.
+    pub async fn snapshot(&self, device: &Device) -> Result<DeviceSnapshot> {
+        let known = self
+            .states
+            .lock()
+            .expect("states lock poisoned")
+            .get(&device.name)
+            .cloned();
+
+        match known {
+            Some(snapshot) => Ok(snapshot),
+            None => self.refresh(device).await,
+        }
+    }
+
+    /// Fetch the current state of `device`, ignoring anything remembered.
+    ///
+    /// Long-running modes use this, since the device can be changed from
+    /// elsewhere between polls.
+    pub async fn refresh(&self, device: &Device) -> Result<DeviceSnapshot> {
+        let _permits = self.acquire(device).await;
+
+        let res = self
+            .bounded(async {
+                match self.route(device) {
+                    Route::Cloud => self
+                        .throttled(|| self.cloud.state(device))
+                        .await
+                        .map(|state| DeviceSnapshot::from(&state)),
+                    Route::Lan(ip) => self
+                        .lan
+                        .status(ip)
+                        .await
+                        .map(|status| DeviceSnapshot::from(&status)),
+                    Route::Auto(ip) => match self.lan.status(ip).await {
+                        Ok(status) => Ok(DeviceSnapshot::from(&status)),
+                        Err(_) => self
+                            .throttled(|| self.cloud.state(device))
+                            .await
+                            .map(|state| DeviceSnapshot::from(&state)),
+                    },
+                }
+            })
+            .await;
+
+        if let Ok(ref snapshot) = res {
+            self.states
+                .lock()
+                .expect("states lock poisoned")
+                .insert(device.name.clone(), snapshot.clone());
+        }
+
+        self.record(device, &res);
+        res
+    }
+
+    /// Apply the device's color correction, falling back to its model's.
+    fn correct(&self, device: &Device, color: Color) -> Result<Color> {
+        let correction = self
+            .device_corrections
+            .get(&device.name)
+            .or_else(|| self.model_corrections.get(&device.model));
+
+        match correction {
+            Some(correction) => correction
+                .apply(Rgb::new(color.r, color.g, color.b))
+                .to_color(),
+            None => Ok(color),
+        }
+    }
+
+    /// Drop the remembered state of `device` after changing it.
+    fn forget(&self, device: &Device) {
+        self.states
+            .lock()
+            .expect("states lock poisoned")
+            .remove(&device.name);
+    }
+
+    /// Note the outcome of a change to `device` for the result summary.
+    fn note(&self, device: &Device, action: String, latency: Duration, res: &Result<()>) {
+        let mut results = self.results.lock().expect("results lock poisoned");
+        let result = results.entry(device.name.clone()).or_default();
+
+        result.action = action;
+        result.latency = latency;
+        result.ops += 1;
+        if let Err(e) = res {
+            result.failed += 1;
+            result.error = Some(format!("{:#}", e));
+        }
+    }
+
+    /// A table of what happened to each of `devices`, when more than one was
+    /// changed and the client isn't quiet.
+    pub fn result_summary(&self, devices: &Devices) -> Option<String> {
+        let results = self.results.lock().expect("results lock poisoned");
+
+        if self.quiet || devices.devices.len() < 2 || results.is_empty() {
+            return None;
+        }
+
+        let mut lines = vec![format!(
+            "{:<24} {:<20} {:<10} {:>8}  {}",
+            "device", "action", "result", "latency", "error"
+        )];
+        let mut ok = 0;
+
+        for device in devices.iter() {
+            let line = match results.get(&device.name) {
+                Some(result) => {
+                    let outcome = match result.failed {
+                        0 => {
+                            ok += 1;
+                            "ok".to_string()
+                        }
+                        failed if failed == result.ops => "failed".to_string(),
+                        failed => format!("{}/{} failed", failed, result.ops),
+                    };
+
+                    let latency = format!("{}ms", result.latency.as_millis());
+
+                    format!(
+                        "{:<24} {:<20} {:<10} {:>8}  {}",
+                        device.name,
+                        result.action,
+                        outcome,
+                        latency,
+                        result.error.as_deref().unwrap_or_default()
+                    )
+                }
+                None => format!("{:<24} {:<20} {:<10}", device.name, "-", "skipped"),
+            };
+            lines.push(line);
+        }
+
+        lines.push(format!("{} of {} devices ok", ok, devices.devices.len()));
+
+        Some(lines.join("\n"))
+    }
+
+    /// Note the outcome of an operation on `device` in the health file.
+    fn record<T>(&self, device: &Device, res: &Result<T>) {
+        if res.is_ok() {
+            self.completed
+                .lock()
+                .expect("completed lock poisoned")
+                .insert(device.name.clone());
+        }
+
+        // being cut off says nothing about the device
+        if res.is_err() && self.stopped() {
+            return;
+        }
+
+        let due = {
+            let mut health = self.health.lock().expect("health lock poisoned");
+            health.outcomes.push((
+                device.name.clone(),
+                Outcome::now(res.as_ref().err().map(|e| format!("{:#}", e))),
+            ));
+            health.flushed.elapsed() >= HEALTH_FLUSH_INTERVAL
+        };
+
+        if due {
+            self.flush_health();
+        }
+    }
+}
+
+impl Shared {
+    /// Write any recorded outcomes to the health file.
+    ///
+    /// This happens periodically and when the last clone of the client is
+    /// dropped, so it only needs calling directly before exiting without
+    /// unwinding.
+    pub fn flush_health(&self) {
+        let outcomes = {
+            let mut health = self.health.lock().expect("health lock poisoned");
+            health.flushed = Instant::now();
+            std::mem::take(&mut health.outcomes)
+        };
+
+        if outcomes.is_empty() {
+            return;
+        }
+
+        if let Err(e) = Health::append(outcomes) {
+            eprintln!("Could not save device health: {:#}", e);
+        }
+    }
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        self.flush_health();
+    }
+}
+
+/// Whether `e` is the api telling us to slow down.
+///
+/// The cloud client doesn't expose status codes, so this goes by the message.
+fn is_rate_limit<E: std::error::Error>(e: &E) -> bool {
+    let msg = e.to_string();
+    msg.contains("429") || msg.to_lowercase().contains("too many requests")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{scene::Scenes, sequence};
+
+    fn limits() -> BrightnessLimits {
+        let settings: Vec<DeviceSetting> = [
+            "name = 'stairs'\nmin_brightness = 5",
+            "name = 'bedroom'\nmax_brightness = 60",
+            "name = 'desk'",
+        ]
+        .iter()
+        .map(|raw| toml::from_str(raw).unwrap())
+        .collect();
+
+        BrightnessLimits::from_settings(&settings)
+    }
+
+    #[test]
+    fn sequence_steps_are_clamped() {
+        let limits = limits();
+        let keyframes =
+            sequence::parse_csv("at,device,brightness\n0,stairs,1\n1,bedroom,100\n2,desk,100\n")
+                .unwrap();
+
+        let sent: Vec<u8> = keyframes
+            .iter()
+            .map(|k| limits.apply(k.device.as_deref().unwrap(), k.brightness.unwrap()))
+            .collect();
+
+        assert_eq!(sent, vec![5, 60, 100]);
+    }
+
+    #[test]
+    fn scene_states_are_clamped() {
+        let limits = limits();
+        let scenes: Scenes = toml::from_str(
+            r#"
+            [scenes.night.stairs]
+            power = true
+            brightness = 0
+
+            [scenes.night.bedroom]
+            power = true
+            brightness = 90
+            "#,
+        )
+        .unwrap();
+
+        let night = scenes.get("night").unwrap();
+        let sent = |name: &str| limits.apply(name, night[name].brightness.unwrap());
+
+        assert_eq!(sent("stairs"), 5);
+        assert_eq!(sent("bedroom"), 60);
+    }
+
+    #[test]
+    fn invalid_limits_are_ignored() {
+        let setting: DeviceSetting =
+            toml::from_str("name = 'hall'\nmin_brightness = 80\nmax_brightness = 20").unwrap();
+        let limits = BrightnessLimits::from_settings([&setting]);
+
+        assert_eq!(limits.apply("hall", 50), 50);
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::{anyhow, Result};
+use std::fmt;
+
+use govee_rs::models::Color;
+use serde::{Deserialize, Serialize};
+
+/// A plain 8-bit rgb triple for doing color math before handing colors to
+/// the api.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Parse a #rrggbb (or rrggbb) hex string.
+    pub fn parse(s: &str) -> Result<Self> {
+        let hex = s.trim_start_matches('#');
+        if hex.len() != 6 || !hex.is_ascii() {
+            return Err(anyhow!("Invalid color '{}', expected #rrggbb", s));
+        }
+
+        let channel = |i: usize| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| anyhow!("Invalid color '{}', expected #rrggbb", s))
+        };
+
+        Ok(Self::new(channel(0)?, channel(2)?, channel(4)?))
+    }
+
+    /// Approximate the color of a black body at `kelvin`.
+    ///
+    /// This is Tanner Helland's fit, which is good enough for tinting lights
+    /// between roughly 1000K and 40000K.
+    pub fn from_kelvin(kelvin: u32) -> Self {
+        let temp = f64::from(kelvin.clamp(1000, 40000)) / 100.0;
+
+        let r = if temp <= 66.0 {
+            255.0
+        } else {
+            329.698_727_446 * (temp - 60.0).powf(-0.133_204_759_2)
+        };
+
+        let g = if temp <= 66.0 {
+            99.470_802_586_1 * temp.ln() - 161.119_568_166_1
+        } else {
+            288.122_169_528_3 * (temp - 60.0).powf(-0.075_514_849_2)
+        };
+
+        let b = if temp >= 66.0 {
+            255.0
+        } else if temp <= 19.0 {
+            0.0
+        } else {
+            138.517_731_223_1 * (temp - 10.0).ln() - 305.044_792_730_7
+        };
+
+        let clamp = |v: f64| v.round().clamp(0.0, 255.0) as u8;
+
+        Self::new(clamp(r), clamp(g), clamp(b))
+    }
+
+    /// The color `t` of the way from this one to `other`, with `t` between 0
+    /// and 1.
+    pub fn lerp(self, other: Self, t: f64) -> Self {
+        let mix = |a: u8, b: u8| {
+            (f64::from(a) + (f64::from(b) - f64::from(a)) * t)
+                .round()
+                .clamp(0.0, 255.0) as u8
+        };
+
+        Self::new(
+            mix(self.r, other.r),
+            mix(self.g, other.g),
+            mix(self.b, other.b),
+        )
+    }
+
+    /// The color as seen with `deficiency`, using the Machado et al. (2009)
+    /// simulation at full severity.
+    pub fn simulate(self, deficiency: Deficiency) -> Self {
+        let m = deficiency.matrix();
+        let [r, g, b] = self.linear();
+
+        Self::from_linear([
+            m[0][0] * r + m[0][1] * g + m[0][2] * b,
+            m[1][0] * r + m[1][1] * g + m[1][2] * b,
+            m[2][0] * r + m[2][1] * g + m[2][2] * b,
+        ])
+    }
+
+    /// The perceptual distance to `other`, as CIE76 Delta-E.
+    ///
+    /// Around 2.3 is barely noticeable side by side; lights glanced at from
+    /// across a room need a lot more than that.
+    pub fn delta_e(self, other: Self) -> f64 {
+        let a = self.to_lab();
+        let b = other.to_lab();
+
+        ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+    }
+
+    fn to_lab(self) -> [f64; 3] {
+        let [r, g, b] = self.linear();
+
+        // D65 reference white
+        let x = (0.412_456_4 * r + 0.357_576_1 * g + 0.180_437_5 * b) / 0.950_47;
+        let y = 0.212_672_9 * r + 0.715_152_2 * g + 0.072_175_0 * b;
+        let z = (0.019_333_9 * r + 0.119_192_0 * g + 0.950_304_1 * b) / 1.088_83;
+
+        let f = |t: f64| {
+            if t > 216.0 / 24389.0 {
+                t.cbrt()
+            } else {
+                (24389.0 / 27.0 * t + 16.0) / 116.0
+            }
+        };
+
+        let (fx, fy, fz) = (f(x), f(y), f(z));
+
+        [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+    }
+
+    fn linear(self) -> [f64; 3] {
+        let channel = |c: u8| {
+            let c = f64::from(c) / 255.0;
+            if c <= 0.040_45 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        };
+
+        [channel(self.r), channel(self.g), channel(self.b)]
+    }
+
+    fn from_linear(rgb: [f64; 3]) -> Self {
+        let channel = |c: f64| {
+            let c = c.clamp(0.0, 1.0);
+            let c = if c <= 0.003_130_8 {
+                c * 12.92
+            } else {
+                1.055 * c.powf(1.0 / 2.4) - 0.055
+            };
+            (c * 255.0).round() as u8
+        };
+
+        Self::new(channel(rgb[0]), channel(rgb[1]), channel(rgb[2]))
+    }
+
+    pub fn to_color(self) -> Result<Color> {
+        Ok(Color::parse(&self.to_string())?)
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+/// A common color-vision deficiency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Deficiency {
+    Protanopia,
+    Deuteranopia,
+    Tritanopia,
+}
+
+impl Deficiency {
+    pub const ALL: [Self; 3] = [Self::Protanopia, Self::Deuteranopia, Self::Tritanopia];
+
+    fn matrix(self) -> [[f64; 3]; 3] {
+        match self {
+            Self::Protanopia => [
+                [0.152_286, 1.052_583, -0.204_868],
+                [0.114_503, 0.786_281, 0.099_216],
+                [-0.003_882, -0.048_116, 1.051_998],
+            ],
+            Self::Deuteranopia => [
+                [0.367_322, 0.860_646, -0.227_968],
+                [0.280_085, 0.672_501, 0.047_413],
+                [-0.011_820, 0.042_940, 0.968_881],
+            ],
+            Self::Tritanopia => [
+                [1.255_528, -0.076_749, -0.178_779],
+                [-0.078_411, 0.930_809, 0.147_602],
+                [0.004_733, 0.691_367, 0.303_900],
+            ],
+        }
+    }
+}
+
+impl fmt::Display for Deficiency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Protanopia => "protanopia",
+            Self::Deuteranopia => "deuteranopia",
+            Self::Tritanopia => "tritanopia",
+        };
+
+        write!(f, "{}", name)
+    }
+}
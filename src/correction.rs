@@ -0,0 +1,66 @@
+use anyhow::{bail, Context, Result};
+
+use crate::{color::Rgb, settings::CorrectionSetting};
+
+/// A per-device or per-model adjustment applied to every color before it's
+/// sent, so the same color looks alike across a mixed group.
+#[derive(Debug, Clone, Default)]
+pub struct Correction {
+    gamma: Option<f64>,
+    white: Option<Rgb>,
+    lut: Vec<(Rgb, Rgb)>,
+}
+
+impl Correction {
+    pub fn from_setting(setting: &CorrectionSetting) -> Result<Self> {
+        if let Some(gamma) = setting.gamma {
+            if gamma.is_nan() || gamma <= 0.0 {
+                bail!("Invalid gamma {}, must be positive", gamma);
+            }
+        }
+
+        let white = setting
+            .white
+            .as_deref()
+            .map(Rgb::parse)
+            .transpose()
+            .context("Invalid white point")?;
+
+        let mut lut = Vec::new();
+        for (from, to) in setting.lut.iter().flatten() {
+            let from = Rgb::parse(from).context("Invalid lookup table entry")?;
+            let to = Rgb::parse(to).context("Invalid lookup table entry")?;
+            lut.push((from, to));
+        }
+
+        Ok(Self {
+            gamma: setting.gamma,
+            white,
+            lut,
+        })
+    }
+
+    /// The color to send in place of `color`.
+    ///
+    /// A lookup table entry for the exact color wins outright, otherwise the
+    /// channels are scaled by the white point and then gamma adjusted.
+    pub fn apply(&self, color: Rgb) -> Rgb {
+        if let Some((_, to)) = self.lut.iter().find(|(from, _)| *from == color) {
+            return *to;
+        }
+
+        let white = self.white.unwrap_or(Rgb::new(255, 255, 255));
+        let gamma = self.gamma.unwrap_or(1.0);
+
+        let channel = |c: u8, w: u8| {
+            let scaled = f64::from(c) / 255.0 * f64::from(w) / 255.0;
+            (scaled.powf(gamma) * 255.0).round().clamp(0.0, 255.0) as u8
+        };
+
+        Rgb::new(
+            channel(color.r, white.r),
+            channel(color.g, white.g),
+            channel(color.b, white.b),
+        )
+    }
+}
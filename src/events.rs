@@ -0,0 +1,144 @@
+use anyhow::{bail, Context, Result};
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::Local;
+
+/// How often `follow` checks the log for new lines.
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How large the log may grow before it is moved aside to `events.log.1`
+/// (replacing the previous one) and started afresh.
+const MAX_LOG_BYTES: u64 = 1024 * 1024;
+
+/// The event log, `spirit/events.log` in the local data directory.
+pub fn path() -> Result<PathBuf> {
+    match dirs::data_local_dir() {
+        Some(dir) => Ok(dir.join("spirit").join("events.log")),
+        None => bail!("Could not determine local data directory for event log"),
+    }
+}
+
+/// Where the log is moved when it gets too large.
+fn rotated_path(path: &Path) -> PathBuf {
+    path.with_extension("log.1")
+}
+
+/// Append an event to the log.
+///
+/// Long-running modes use this to leave a trail of what they did. Failing to
+/// write the log is reported but never fails the caller.
+pub fn record(kind: &str, message: impl Display) {
+    if let Err(e) = try_record(kind, message) {
+        eprintln!("Could not write event log: {:#}", e);
+    }
+}
+
+fn try_record(kind: &str, message: impl Display) -> Result<()> {
+    let path = path()?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    if fs::metadata(&path).map(|m| m.len()).unwrap_or(0) >= MAX_LOG_BYTES {
+        fs::rename(&path, rotated_path(&path))
+            .with_context(|| format!("Could not rotate event log {}", path.display()))?;
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(
+        file,
+        "{} {:<8} {}",
+        Local::now().format("%Y-%m-%d %H:%M:%S"),
+        kind,
+        message
+    )?;
+
+    Ok(())
+}
+
+/// Print the last `lines` lines of the log, then keep printing new lines as
+/// they are written if `follow` is set.
+pub async fn dump(lines: usize, follow: bool) -> Result<()> {
+    let path = path()?;
+
+    if !path.exists() && !follow {
+        return Ok(());
+    }
+
+    let mut offset = 0;
+
+    if path.exists() {
+        // only the last `lines` lines are kept in memory, reaching back into
+        // the rotated log if the current one is short
+        let mut tail = VecDeque::with_capacity(lines);
+        let rotated = rotated_path(&path);
+
+        for source in [&rotated, &path] {
+            let file = match File::open(source) {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+
+            let mut reader = BufReader::new(file);
+            let mut line = String::new();
+            let mut read = 0;
+
+            loop {
+                line.clear();
+                let len = reader
+                    .read_line(&mut line)
+                    .with_context(|| format!("Could not read event log {}", source.display()))?;
+                if len == 0 {
+                    break;
+                }
+                read += len as u64;
+
+                if lines > 0 {
+                    if tail.len() == lines {
+                        tail.pop_front();
+                    }
+                    tail.push_back(line.trim_end().to_string());
+                }
+            }
+
+            if source == &path {
+                offset = read;
+            }
+        }
+
+        for line in tail {
+            println!("{}", line);
+        }
+    }
+
+    while follow {
+        tokio::time::sleep(FOLLOW_INTERVAL).await;
+
+        let mut file = match File::open(&path) {
+            Ok(file) => file,
+            Err(_) => continue,
+        };
+
+        let len = file.metadata()?.len();
+        if len < offset {
+            // the log was truncated or replaced, start over
+            offset = 0;
+        }
+
+        if len > offset {
+            file.seek(SeekFrom::Start(offset))?;
+            let mut new = String::new();
+            file.read_to_string(&mut new)?;
+            print!("{}", new);
+            offset = len;
+        }
+    }
+
+    Ok(())
+}
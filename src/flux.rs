@@ -0,0 +1,77 @@
+use anyhow::{anyhow, bail, Result};
+use chrono::{NaiveTime, Timelike};
+
+use crate::settings::FluxPoint;
+
+/// A daily color temperature curve.
+///
+/// Points are interpolated linearly and the curve wraps around midnight, so
+/// the last point of the day blends into the first.
+#[derive(Debug, Clone)]
+pub struct Curve {
+    /// (seconds from midnight, kelvin), sorted by time.
+    points: Vec<(u32, u32)>,
+}
+
+impl Curve {
+    pub fn from_settings(points: &[FluxPoint]) -> Result<Self> {
+        if points.is_empty() {
+            bail!("A flux curve needs at least one point");
+        }
+
+        let mut parsed = Vec::with_capacity(points.len());
+        for point in points {
+            let time = NaiveTime::parse_from_str(&point.time, "%H:%M").map_err(|e| {
+                anyhow!("Invalid flux time '{}' (expected HH:MM): {}", point.time, e)
+            })?;
+            parsed.push((time.num_seconds_from_midnight(), point.kelvin));
+        }
+
+        parsed.sort_unstable();
+
+        Ok(Self { points: parsed })
+    }
+
+    /// Cool through the middle of the day, warm in the evening and at night.
+    pub fn default_curve() -> Self {
+        let hours = |h: u32| h * 3600;
+
+        Self {
+            points: vec![
+                (hours(6), 2700),
+                (hours(9), 5000),
+                (hours(12), 6500),
+                (hours(17), 5000),
+                (hours(20), 3000),
+                (hours(22), 2700),
+            ],
+        }
+    }
+
+    pub fn kelvin_at(&self, time: NaiveTime) -> u32 {
+        const DAY: u32 = 24 * 3600;
+
+        let now = time.num_seconds_from_midnight();
+
+        // the points on either side of now, wrapping around midnight
+        let next_idx = self.points.iter().position(|(t, _)| *t > now).unwrap_or(0);
+        let prev_idx = if next_idx == 0 {
+            self.points.len() - 1
+        } else {
+            next_idx - 1
+        };
+
+        let (prev_t, prev_k) = self.points[prev_idx];
+        let (next_t, next_k) = self.points[next_idx];
+
+        let span = (next_t + DAY - prev_t) % DAY;
+        if span == 0 {
+            return prev_k;
+        }
+
+        let elapsed = (now + DAY - prev_t) % DAY;
+        let frac = f64::from(elapsed) / f64::from(span);
+
+        (f64::from(prev_k) + (f64::from(next_k) - f64::from(prev_k)) * frac).round() as u32
+    }
+}
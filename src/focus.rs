@@ -0,0 +1,117 @@
+use crate::settings::DndMode;
+
+/// How an attention effect (a flash or a blinking pattern) should play, given the
+/// `dnd` setting and whether do-not-disturb is on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attention {
+    /// Play the effect as asked.
+    Full,
+    /// Play the effect once, without repeats.
+    Once,
+    /// Don't play the effect at all.
+    Skip,
+}
+
+impl Attention {
+    pub fn for_mode(mode: DndMode) -> Self {
+        match mode {
+            DndMode::Ignore => Self::Full,
+            _ if !do_not_disturb() => Self::Full,
+            DndMode::Downgrade => Self::Once,
+            DndMode::Suppress => Self::Skip,
+        }
+    }
+
+    /// `repeats` adjusted for this level, or None to skip the effect.
+    pub fn repeats(self, repeats: usize) -> Option<usize> {
+        match self {
+            Self::Full => Some(repeats),
+            Self::Once => Some(repeats.min(1)),
+            Self::Skip => None,
+        }
+    }
+}
+
+/// Whether the desktop's do-not-disturb (or focus) mode is on.
+///
+/// This asks GNOME's notification settings, then the freedesktop
+/// notification daemon's `Inhibited` property (KDE and others). Anything
+/// that can't be asked counts as off.
+#[cfg(target_os = "linux")]
+pub fn do_not_disturb() -> bool {
+    if let Some(banners) = output(
+        "gsettings",
+        &["get", "org.gnome.desktop.notifications", "show-banners"],
+    ) {
+        if banners.trim() == "false" {
+            return true;
+        }
+    }
+
+    output(
+        "gdbus",
+        &[
+            "call",
+            "--session",
+            "--dest",
+            "org.freedesktop.Notifications",
+            "--object-path",
+            "/org/freedesktop/Notifications",
+            "--method",
+            "org.freedesktop.DBus.Properties.Get",
+            "org.freedesktop.Notifications",
+            "Inhibited",
+        ],
+    )
+    .map(|reply| reply.contains("true"))
+    .unwrap_or(false)
+}
+
+/// Whether a focus mode is on.
+///
+/// macOS has no public api for this, so it looks for active assertions in
+/// the focus database, which needs the calling terminal to have full disk
+/// access. Anything that can't be read counts as off.
+#[cfg(target_os = "macos")]
+pub fn do_not_disturb() -> bool {
+    let path = match dirs::home_dir() {
+        Some(home) => home.join("Library/DoNotDisturb/DB/Assertions.json"),
+        None => return false,
+    };
+
+    let assertions: serde_json::Value = match std::fs::read_to_string(path)
+        .ok()
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+    {
+        Some(assertions) => assertions,
+        None => return false,
+    };
+
+    assertions["data"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .any(|entry| {
+            entry["storeAssertionRecords"]
+                .as_array()
+                .map(|records| !records.is_empty())
+                .unwrap_or(false)
+        })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn do_not_disturb() -> bool {
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let out = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    String::from_utf8(out.stdout).ok()
+}
@@ -0,0 +1,151 @@
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::path::PathBuf;
+
+use chrono::Utc;
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+
+/// How many outcomes are kept per device.
+const HISTORY: usize = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Outcome {
+    /// Seconds since the unix epoch.
+    pub at: i64,
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Outcome {
+    /// An outcome happening now, failed if there's an `error`.
+    pub fn now(error: Option<String>) -> Self {
+        Self {
+            at: Utc::now().timestamp(),
+            ok: error.is_none(),
+            error,
+        }
+    }
+}
+
+/// The most recent command outcomes for each device.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Health {
+    #[serde(default)]
+    pub devices: BTreeMap<String, Vec<Outcome>>,
+}
+
+/// A summary of one device's recent outcomes.
+#[derive(Debug, Clone)]
+pub struct DeviceHealth {
+    pub name: String,
+    pub total: usize,
+    pub failed: usize,
+    pub last_error: Option<String>,
+}
+
+impl DeviceHealth {
+    pub fn failure_rate(&self) -> f64 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.failed as f64 / self.total as f64
+        }
+    }
+}
+
+impl Health {
+    /// The health file, `spirit/health.toml` in the local data directory.
+    pub fn path() -> Result<PathBuf> {
+        match dirs::data_local_dir() {
+            Some(dir) => Ok(dir.join("spirit").join("health.toml")),
+            None => bail!("Could not determine local data directory for health file"),
+        }
+    }
+
+    /// Load the health file, treating a missing file as empty.
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let raw = fs::read_to_string(&path)
+            .with_context(|| format!("Could not read health file {}", path.display()))?;
+
+        toml::from_str(&raw)
+            .with_context(|| format!("Could not parse health file {}", path.display()))
+    }
+
+    /// Add `outcomes` to the health file.
+    ///
+    /// Several spirit processes can be recording at once, so the file is
+    /// re-read and written back while holding a lock on it, and the write
+    /// goes through a temporary file so readers never see half of it.
+    pub fn append(outcomes: Vec<(String, Outcome)>) -> Result<()> {
+        let path = Self::path()?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let lock_path = path.with_extension("lock");
+        let lock = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(&lock_path)
+            .with_context(|| format!("Could not open {}", lock_path.display()))?;
+        lock.lock_exclusive()
+            .with_context(|| format!("Could not lock {}", lock_path.display()))?;
+
+        let mut health = Self::load()?;
+        for (name, outcome) in outcomes {
+            health.push(&name, outcome);
+        }
+
+        let tmp = path.with_extension("toml.tmp");
+        fs::write(&tmp, toml::to_string_pretty(&health)?)
+            .with_context(|| format!("Could not write health file {}", tmp.display()))?;
+        fs::rename(&tmp, &path)
+            .with_context(|| format!("Could not write health file {}", path.display()))
+
+        // the lock is released when `lock` is closed
+    }
+
+    fn push(&mut self, name: &str, outcome: Outcome) {
+        let outcomes = self.devices.entry(name.to_string()).or_default();
+
+        outcomes.push(outcome);
+
+        if outcomes.len() > HISTORY {
+            let excess = outcomes.len() - HISTORY;
+            outcomes.drain(..excess);
+        }
+    }
+
+    /// Per-device summaries, worst first.
+    pub fn summaries(&self) -> Vec<DeviceHealth> {
+        let mut out: Vec<DeviceHealth> = self
+            .devices
+            .iter()
+            .map(|(name, outcomes)| DeviceHealth {
+                name: name.clone(),
+                total: outcomes.len(),
+                failed: outcomes.iter().filter(|o| !o.ok).count(),
+                last_error: outcomes.iter().rev().find_map(|o| o.error.clone()),
+            })
+            .collect();
+
+        out.sort_by(|a, b| {
+            b.failure_rate()
+                .partial_cmp(&a.failure_rate())
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+
+        out
+    }
+}
@@ -0,0 +1,281 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use rusqlite::{params, Connection};
+
+use crate::state::DeviceSnapshot;
+
+/// How long samples are kept before `open` prunes them.
+const RETENTION: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+/// How many poll intervals may pass between samples before the device is
+/// taken to have gone unwatched (watch stopped, the machine slept) rather
+/// than to have stayed as it was.
+const GAP_INTERVALS: u64 = 3;
+
+/// A device's state at one poll.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// Seconds since the unix epoch.
+    pub at: u64,
+    /// Seconds until the next poll was due.
+    pub interval: u64,
+    pub snapshot: DeviceSnapshot,
+}
+
+/// A stretch of time a device spent in one state.
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub from: u64,
+    pub to: u64,
+    pub snapshot: DeviceSnapshot,
+}
+
+impl Span {
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.to.saturating_sub(self.from))
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.snapshot.online != Some(false) && self.snapshot.power == Some(true)
+    }
+}
+
+/// Device states sampled by long-running modes, kept in a local sqlite
+/// database.
+pub struct History {
+    conn: Connection,
+}
+
+impl History {
+    /// The database, `spirit/history.sqlite3` in the local data directory.
+    pub fn path() -> Result<PathBuf> {
+        match dirs::data_local_dir() {
+            Some(dir) => Ok(dir.join("spirit").join("history.sqlite3")),
+            None => bail!("Could not determine local data directory for history"),
+        }
+    }
+
+    /// Open (creating if needed) the database, dropping samples older than
+    /// the retention period.
+    pub fn open(now: u64) -> Result<Self> {
+        let path = Self::path()?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let conn = Connection::open(&path)
+            .with_context(|| format!("Could not open history {}", path.display()))?;
+
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS samples (
+                device TEXT NOT NULL,
+                at INTEGER NOT NULL,
+                interval INTEGER NOT NULL,
+                online INTEGER,
+                power INTEGER,
+                brightness INTEGER,
+                color TEXT
+            );
+            CREATE INDEX IF NOT EXISTS samples_device_at ON samples (device, at);",
+        )
+        .context("Could not set up history")?;
+
+        conn.execute(
+            "DELETE FROM samples WHERE at < ?1",
+            params![now.saturating_sub(RETENTION.as_secs()) as i64],
+        )
+        .context("Could not prune history")?;
+
+        Ok(Self { conn })
+    }
+
+    /// Store `snapshot` as sampled at `at`, with the next sample due after
+    /// `interval`.
+    pub fn record(
+        &self,
+        device: &str,
+        snapshot: &DeviceSnapshot,
+        at: u64,
+        interval: Duration,
+    ) -> Result<()> {
+        self.conn
+            .execute(
+                "INSERT INTO samples (device, at, interval, online, power, brightness, color)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    device,
+                    at as i64,
+                    interval.as_secs() as i64,
+                    snapshot.online,
+                    snapshot.power,
+                    snapshot.brightness,
+                    snapshot.color,
+                ],
+            )
+            .context("Could not record history")?;
+
+        Ok(())
+    }
+
+    /// The samples for `device` taken at or after `since`, oldest first.
+    pub fn samples(&self, device: &str, since: u64) -> Result<Vec<Sample>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT at, interval, online, power, brightness, color FROM samples
+            WHERE device = ?1 AND at >= ?2 ORDER BY at",
+        )?;
+
+        let rows = stmt.query_map(params![device, since as i64], |row| {
+            Ok(Sample {
+                at: row.get::<_, i64>(0)? as u64,
+                interval: row.get::<_, i64>(1)? as u64,
+                snapshot: DeviceSnapshot {
+                    online: row.get(2)?,
+                    power: row.get(3)?,
+                    brightness: row.get(4)?,
+                    color: row.get(5)?,
+                },
+            })
+        })?;
+
+        rows.collect::<rusqlite::Result<_>>()
+            .context("Could not read history")
+    }
+}
+
+/// The total time spent on across `spans`.
+pub fn on_time(spans: &[Span]) -> Duration {
+    spans.iter().filter(|s| s.is_on()).map(Span::duration).sum()
+}
+
+/// Roughly how many watt-hours a device drawing `watts` at full brightness
+/// used across `spans`.
+///
+/// Draw is taken to scale linearly with brightness, which is only
+/// approximately true of LEDs but close enough for a cost estimate.
+pub fn energy(spans: &[Span], watts: f64) -> f64 {
+    spans
+        .iter()
+        .filter(|s| s.is_on())
+        .map(|s| {
+            let level = s.snapshot.brightness.map(|b| f64::from(b) / 100.0);
+            watts * level.unwrap_or(1.0) * s.duration().as_secs_f64() / 3600.0
+        })
+        .sum()
+}
+
+/// Collapse consecutive samples in the same state into spans, each lasting
+/// until the next change (or the last sample, for the final span).
+///
+/// Nothing is known about the time between samples that are much further
+/// apart than their poll interval, so a span running into such a gap ends one
+/// interval after its last sample and the gap is left out.
+pub fn spans(samples: &[Sample]) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::new();
+    let mut previous: Option<&Sample> = None;
+
+    for sample in samples {
+        let continues = match (spans.last_mut(), previous) {
+            (Some(span), Some(last)) => {
+                if sample.at.saturating_sub(last.at) > last.interval * GAP_INTERVALS {
+                    span.to = last.at + last.interval;
+                    false
+                } else {
+                    span.to = sample.at;
+                    span.snapshot == sample.snapshot
+                }
+            }
+            _ => false,
+        };
+
+        previous = Some(sample);
+
+        if !continues {
+            spans.push(Span {
+                from: sample.at,
+                to: sample.at,
+                snapshot: sample.snapshot.clone(),
+            });
+        }
+    }
+
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(at: u64, power: bool) -> Sample {
+        Sample {
+            at,
+            interval: 30,
+            snapshot: DeviceSnapshot {
+                online: Some(true),
+                power: Some(power),
+                brightness: Some(50),
+                color: None,
+            },
+        }
+    }
+
+    #[test]
+    fn spans_run_until_the_next_change() {
+        let samples = vec![sample(0, true), sample(30, true), sample(60, false)];
+        let spans = spans(&samples);
+
+        assert_eq!(spans.len(), 2);
+        assert!(spans[0].is_on());
+        assert_eq!((spans[0].from, spans[0].to), (0, 60));
+        assert_eq!((spans[1].from, spans[1].to), (60, 60));
+        assert_eq!(on_time(&spans), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn gaps_are_not_counted() {
+        // on while watched, watch stops for an hour, then off
+        let samples = vec![
+            sample(0, true),
+            sample(30, true),
+            sample(60, true),
+            sample(3600, false),
+            sample(3630, false),
+        ];
+        let spans = spans(&samples);
+
+        assert_eq!(spans.len(), 2);
+        assert_eq!((spans[0].from, spans[0].to), (0, 90));
+        assert_eq!((spans[1].from, spans[1].to), (3600, 3630));
+        assert_eq!(on_time(&spans), Duration::from_secs(90));
+
+        // 100W at half brightness for 90s
+        assert!((energy(&spans, 100.0) - 1.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn gaps_split_spans_in_the_same_state() {
+        let samples = vec![sample(0, true), sample(30, true), sample(1000, true)];
+        let spans = spans(&samples);
+
+        assert_eq!(spans.len(), 2);
+        assert_eq!((spans[0].from, spans[0].to), (0, 60));
+        assert_eq!((spans[1].from, spans[1].to), (1000, 1000));
+    }
+
+    #[test]
+    fn gaps_follow_the_recorded_interval() {
+        let mut samples = vec![sample(0, true), sample(150, true), sample(400, false)];
+        for sample in samples.iter_mut() {
+            sample.interval = 60;
+        }
+
+        let spans = spans(&samples);
+
+        // 150s is within three 60s polls, the following 250s isn't
+        assert_eq!((spans[0].from, spans[0].to), (0, 210));
+        assert_eq!(on_time(&spans), Duration::from_secs(210));
+    }
+}
@@ -0,0 +1,58 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::str::FromStr;
+
+use global_hotkey::{hotkey::HotKey, GlobalHotKeyEvent, GlobalHotKeyManager, HotKeyState};
+use govee_rs::models::Devices;
+use tokio::sync::mpsc;
+
+use crate::{action::Action, client::Client, events, settings::HotkeySetting};
+
+/// Register `bindings` as global shortcuts and dispatch them until the process
+/// is interrupted.
+///
+/// Shortcut strings use the `global-hotkey` syntax, e.g. `"ctrl+alt+KeyL"`.
+/// Events are delivered from the platform's hotkey thread, which currently
+/// means X11 on Linux; Windows and macOS require an event loop spirit does not
+/// run.
+pub async fn listen(client: &Client, devices: &Devices, bindings: &[HotkeySetting]) -> Result<()> {
+    if bindings.is_empty() {
+        bail!("No hotkeys configured");
+    }
+
+    let manager = GlobalHotKeyManager::new()?;
+    let mut actions = Vec::with_capacity(bindings.len());
+
+    for binding in bindings {
+        let hotkey = HotKey::from_str(&binding.keys)
+            .map_err(|e| anyhow!("Invalid hotkey '{}': {}", binding.keys, e))?;
+        let id = hotkey.id();
+        manager.register(hotkey)?;
+        let action = Action::from_setting(&binding.action)
+            .with_context(|| format!("Invalid action for hotkey '{}'", binding.keys))?;
+        actions.push((binding.keys.as_str(), id, action));
+        println!("Registered {} -> {}", binding.keys, binding.action.action);
+    }
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    tokio::task::spawn_blocking(move || {
+        let receiver = GlobalHotKeyEvent::receiver();
+        while let Ok(event) = receiver.recv() {
+            if event.state == HotKeyState::Pressed && tx.send(event.id).is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Some(id) = rx.recv().await {
+        if let Some((keys, _, action)) = actions.iter().find(|(_, hotkey, _)| *hotkey == id) {
+            events::record("hotkey", keys);
+            if let Err(e) = action.run(client, devices).await {
+                events::record("error", format!("hotkey {} failed: {:#}", keys, e));
+                eprintln!("Hotkey action failed: {:#}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
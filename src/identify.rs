@@ -0,0 +1,39 @@
+use anyhow::Result;
+use std::time::Duration;
+
+use govee_rs::models::{Color, Device, PowerState};
+
+use crate::{capability, client::Client};
+
+/// Flash `device` between `color` and off `times` times, then put it back
+/// the way it was. Devices without color just switch on and off.
+pub async fn flash(
+    client: &Client,
+    device: &Device,
+    color: &Color,
+    times: usize,
+    interval: Duration,
+) -> Result<()> {
+    let previous = client.snapshot(device).await?;
+
+    let res = async {
+        for _ in 0..times {
+            if capability::has_color(device) {
+                client.color(device, color.clone()).await?;
+            } else {
+                client.turn(device, PowerState::On).await?;
+            }
+            tokio::time::sleep(interval).await;
+            client.turn(device, PowerState::Off).await?;
+            tokio::time::sleep(interval).await;
+        }
+
+        Ok::<(), anyhow::Error>(())
+    }
+    .await;
+
+    // restore even if flashing failed or was cancelled part way through
+    previous.apply(&client.restoring(), device).await?;
+
+    res
+}
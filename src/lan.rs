@@ -0,0 +1,201 @@
+use anyhow::{bail, Context, Result};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use tokio::{
+    net::UdpSocket,
+    sync::{Mutex, MutexGuard},
+    time::{sleep, timeout, timeout_at, Instant},
+};
+
+use crate::color::Rgb;
+
+/// The port devices listen on for control commands.
+pub const CONTROL_PORT: u16 = 4003;
+
+/// The port devices send responses to.
+pub const RESPONSE_PORT: u16 = 4002;
+
+/// The multicast group devices listen on for scan requests.
+pub const SCAN_ADDR: SocketAddr =
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(239, 255, 255, 250)), 4001);
+
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long to wait for the response port to free up, whether it's held by
+/// another request in this process or by another spirit process.
+const PORT_WAIT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Deserialize)]
+struct Envelope<T> {
+    msg: Message<T>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Message<T> {
+    cmd: String,
+    data: T,
+}
+
+/// The response to a `devStatus` request.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LanStatus {
+    pub on_off: u8,
+    pub brightness: u8,
+    pub color: Rgb,
+}
+
+/// The response to a `scan` request.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LanDevice {
+    pub ip: IpAddr,
+    /// The device id, which matches the id reported by the cloud api.
+    pub device: String,
+    pub sku: String,
+}
+
+/// A client for the govee LAN api.
+///
+/// The LAN api is a handful of json messages over UDP. Control messages are
+/// fire-and-forget, so only status and scan requests can detect an
+/// unreachable device.
+///
+/// Devices always answer on a fixed port, so status and scan requests take
+/// turns on it. Clones share the turn.
+#[derive(Debug, Default, Clone)]
+pub struct LanClient {
+    response_port: Arc<Mutex<()>>,
+}
+
+impl LanClient {
+    async fn send(&self, ip: IpAddr, cmd: &str, data: Value) -> Result<()> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+        let msg = json!({ "msg": { "cmd": cmd, "data": data } });
+
+        socket
+            .send_to(msg.to_string().as_bytes(), (ip, CONTROL_PORT))
+            .await
+            .with_context(|| format!("Could not send {} to {}", cmd, ip))?;
+
+        Ok(())
+    }
+
+    pub async fn turn(&self, ip: IpAddr, on: bool) -> Result<()> {
+        self.send(ip, "turn", json!({ "value": if on { 1 } else { 0 } }))
+            .await
+    }
+
+    pub async fn brightness(&self, ip: IpAddr, value: u8) -> Result<()> {
+        self.send(ip, "brightness", json!({ "value": value.clamp(1, 100) }))
+            .await
+    }
+
+    pub async fn color(&self, ip: IpAddr, r: u8, g: u8, b: u8) -> Result<()> {
+        self.send(
+            ip,
+            "colorwc",
+            json!({ "color": { "r": r, "g": g, "b": b }, "colorTemInKelvin": 0 }),
+        )
+        .await
+    }
+
+    /// Set a white of `kelvin`. Devices with white LEDs use them for it, the
+    /// color is only there for devices that ignore the temperature.
+    pub async fn color_temperature(&self, ip: IpAddr, kelvin: u32) -> Result<()> {
+        let approx = Rgb::from_kelvin(kelvin);
+        self.send(
+            ip,
+            "colorwc",
+            json!({
+                "color": { "r": approx.r, "g": approx.g, "b": approx.b },
+                "colorTemInKelvin": kelvin,
+            }),
+        )
+        .await
+    }
+
+    /// Bind the response port, waiting for this process's other requests and
+    /// then for other processes to let go of it.
+    async fn bind_response_port(&self) -> Result<(MutexGuard<'_, ()>, UdpSocket)> {
+        let deadline = Instant::now() + PORT_WAIT;
+
+        let turn = timeout_at(deadline, self.response_port.lock())
+            .await
+            .context("Timed out waiting for the LAN response port")?;
+
+        loop {
+            match UdpSocket::bind((Ipv4Addr::UNSPECIFIED, RESPONSE_PORT)).await {
+                Ok(socket) => return Ok((turn, socket)),
+                Err(e) if e.kind() == ErrorKind::AddrInUse && Instant::now() < deadline => {
+                    sleep(Duration::from_millis(100)).await;
+                }
+                Err(e) => return Err(e).context("Could not bind LAN response port"),
+            }
+        }
+    }
+
+    pub async fn status(&self, ip: IpAddr) -> Result<LanStatus> {
+        let (_turn, socket) = self.bind_response_port().await?;
+
+        let msg = json!({ "msg": { "cmd": "devStatus", "data": {} } });
+        socket
+            .send_to(msg.to_string().as_bytes(), (ip, CONTROL_PORT))
+            .await?;
+
+        let mut buf = [0; 2048];
+        loop {
+            let (len, from) = timeout(RESPONSE_TIMEOUT, socket.recv_from(&mut buf))
+                .await
+                .with_context(|| format!("Timed out waiting for status from {}", ip))??;
+
+            if from.ip() != ip {
+                continue;
+            }
+
+            return parse("devStatus", &buf[..len]);
+        }
+    }
+
+    /// Multicast a scan request and collect the responses that arrive within
+    /// `wait`.
+    pub async fn scan(&self, wait: Duration) -> Result<Vec<LanDevice>> {
+        let (_turn, socket) = self.bind_response_port().await?;
+
+        let msg = json!({ "msg": { "cmd": "scan", "data": { "account_topic": "reserve" } } });
+        socket
+            .send_to(msg.to_string().as_bytes(), SCAN_ADDR)
+            .await
+            .context("Could not send scan request")?;
+
+        let deadline = Instant::now() + wait;
+        let mut found: Vec<LanDevice> = Vec::new();
+        let mut buf = [0; 2048];
+
+        while let Ok(res) = timeout_at(deadline, socket.recv_from(&mut buf)).await {
+            let (len, _) = res?;
+
+            if let Ok(device) = parse::<LanDevice>("scan", &buf[..len]) {
+                if !found.iter().any(|d| d.device == device.device) {
+                    found.push(device);
+                }
+            }
+        }
+
+        Ok(found)
+    }
+}
+
+fn parse<T: DeserializeOwned>(cmd: &str, raw: &[u8]) -> Result<T> {
+    let envelope: Envelope<T> = serde_json::from_slice(raw)?;
+
+    if envelope.msg.cmd != cmd {
+        bail!("Expected a {} response, got {}", cmd, envelope.msg.cmd);
+    }
+
+    Ok(envelope.msg.data)
+}
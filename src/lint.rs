@@ -0,0 +1,219 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use govee_rs::models::Devices;
+
+use crate::{auto_off::Rule, flux::Curve, settings::Settings};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Something that can never work as written.
+    Error,
+    /// Something that is probably a mistake.
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// The device names, aliases and groups that references can resolve to.
+struct Known<'a> {
+    devices: HashSet<&'a str>,
+    groups: HashSet<&'a str>,
+}
+
+struct Findings(Vec<Finding>);
+
+impl Findings {
+    fn check_refs(&mut self, known: &Known, owner: &str, names: &[String], groups: &[String]) {
+        for name in names {
+            if !known.devices.contains(name.as_str()) {
+                self.warning(format!("{} targets unknown device '{}'", owner, name));
+            }
+        }
+        for group in groups {
+            if !known.groups.contains(group.as_str()) {
+                self.error(format!(
+                    "{} targets group '{}', which no device is in",
+                    owner, group
+                ));
+            }
+        }
+    }
+
+    fn error(&mut self, message: String) {
+        self.0.push(Finding {
+            severity: Severity::Error,
+            message,
+        });
+    }
+
+    fn warning(&mut self, message: String) {
+        self.0.push(Finding {
+            severity: Severity::Warning,
+            message,
+        });
+    }
+}
+
+/// Look for config that can never take effect or that contradicts itself,
+/// checking device references against the account's `devices`.
+///
+/// Findings are sorted with errors first.
+pub fn lint(settings: &Settings, devices: &Devices) -> Vec<Finding> {
+    let mut findings = Findings(Vec::new());
+    let device_settings = settings.device_settings();
+
+    let mut known = Known {
+        devices: devices.iter().map(|d| d.name.as_str()).collect(),
+        groups: HashSet::new(),
+    };
+    for setting in settings.devices.iter().flatten() {
+        if let Some(ref alias) = setting.alias {
+            known.devices.insert(alias);
+        }
+        for group in setting.groups.iter().flatten() {
+            known.groups.insert(group);
+        }
+    }
+
+    if let Some(ref concurrency) = settings.concurrency {
+        let limits = [("cloud", concurrency.cloud), ("lan", concurrency.lan)];
+        for (what, max) in limits {
+            if max == Some(0) {
+                findings.error(format!("concurrency limit for {} is 0", what));
+            }
+        }
+
+        let groups: Vec<String> = concurrency
+            .groups
+            .iter()
+            .flatten()
+            .map(|(g, _)| g.clone())
+            .collect();
+        findings.check_refs(&known, "concurrency", &[], &groups);
+
+        for (group, max) in concurrency.groups.iter().flatten() {
+            if *max == 0 {
+                findings.error(format!("concurrency limit for group '{}' is 0", group));
+            }
+        }
+    }
+
+    for setting in settings.devices.iter().flatten() {
+        if let Err(e) = setting.brightness_range() {
+            findings.error(format!(
+                "device '{}' has invalid brightness limits: {:#}",
+                setting.name, e
+            ));
+        }
+    }
+
+    for check in settings.checks.iter().flatten() {
+        findings.check_refs(
+            &known,
+            &format!("check '{}'", check.name),
+            check.devices.as_deref().unwrap_or_default(),
+            check.groups.as_deref().unwrap_or_default(),
+        );
+    }
+
+    for button in settings.buttons.iter().flatten() {
+        findings.check_refs(
+            &known,
+            &format!("button '{}'", button.name),
+            button.devices.as_deref().unwrap_or_default(),
+            &[],
+        );
+    }
+
+    if let Some(ref keylight) = settings.keylight {
+        if let Some(ref device) = keylight.device {
+            findings.check_refs(&known, "keylight", std::slice::from_ref(device), &[]);
+        }
+    }
+
+    let mut rules = Vec::new();
+    for (i, setting) in settings.auto_off.iter().flatten().enumerate() {
+        let owner = format!("auto_off rule {}", i + 1);
+
+        match Rule::from_setting(setting) {
+            Ok(rule) => {
+                if rule.never_fires() {
+                    findings.error(format!("{} has an empty window and can never fire", owner));
+                }
+                findings.check_refs(&known, &owner, rule.devices(), rule.groups());
+                rules.push((owner, rule));
+            }
+            Err(e) => findings.error(format!("{} is invalid: {:#}", owner, e)),
+        }
+    }
+
+    // rules that disagree about what to do with the same device at the
+    // same time
+    for device in devices.iter() {
+        let setting = device_settings.get(&device.name);
+        let applicable: Vec<&(String, Rule)> = rules
+            .iter()
+            .filter(|(_, rule)| rule.applies_to(device, setting))
+            .collect();
+
+        for (i, (a_name, a)) in applicable.iter().enumerate() {
+            for (b_name, b) in applicable.iter().skip(i + 1) {
+                if a.dim != b.dim && a.overlaps(b) {
+                    findings.warning(format!(
+                        "{} and {} both cover {} at the same time but do different things",
+                        a_name, b_name, device.name
+                    ));
+                }
+            }
+        }
+    }
+
+    if let Some(ref points) = settings.flux {
+        if let Err(e) = Curve::from_settings(points) {
+            findings.error(format!("flux curve is invalid: {:#}", e));
+        }
+    }
+
+    let mut duplicates = |kind: &str, names: Vec<&String>| {
+        let mut seen = HashSet::new();
+        for name in names {
+            if !seen.insert(name) {
+                findings.warning(format!(
+                    "{} '{}' is defined more than once, only the first is used",
+                    kind, name
+                ));
+            }
+        }
+    };
+
+    duplicates(
+        "check",
+        settings.checks.iter().flatten().map(|c| &c.name).collect(),
+    );
+    duplicates(
+        "button",
+        settings.buttons.iter().flatten().map(|b| &b.name).collect(),
+    );
+    duplicates(
+        "signal",
+        settings.signals.iter().flatten().map(|s| &s.name).collect(),
+    );
+
+    let mut findings = findings.0;
+    findings.sort_by_key(|f| f.severity);
+    findings
+}
@@ -1,7 +1,42 @@
 use anyhow::Result;
 
+mod action;
+mod alerts;
+mod auto_off;
+mod cache;
+mod capability;
+mod ci;
 mod cli;
+mod client;
+mod color;
+mod correction;
+mod events;
+mod flux;
+mod focus;
+mod health;
+mod history;
+#[cfg(feature = "hotkeys")]
+mod hotkeys;
+mod identify;
+mod lan;
+mod lint;
+mod names;
+mod nextest;
+mod notify;
+mod palette;
+mod scene;
+mod sequence;
+mod serve;
+mod service;
+mod session;
 mod settings;
+mod signal;
+mod state;
+mod stream;
+mod template;
+mod throttle;
+mod watch;
+mod webcam;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -0,0 +1,163 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+
+use serde_json::Value;
+
+/// The results of a test run, from nextest's libtest-json output.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub passed: usize,
+    /// Tests whose last result was a failure.
+    pub failed: Vec<String>,
+    /// Tests that failed and then passed on a retry.
+    pub flaky: Vec<String>,
+}
+
+impl Summary {
+    /// Read `cargo nextest run --message-format libtest-json` output.
+    ///
+    /// The json events are consumed. Any other lines mixed into the stream
+    /// are copied to `passthrough`, so the runner's own output still shows.
+    pub fn parse(input: impl BufRead, mut passthrough: impl Write) -> Result<Self> {
+        // name -> (ever failed, passed last time)
+        let mut results: BTreeMap<String, (bool, bool)> = BTreeMap::new();
+
+        for line in input.lines() {
+            let line = line?;
+
+            let event: Value = match serde_json::from_str(&line) {
+                Ok(event) => event,
+                Err(_) => {
+                    writeln!(passthrough, "{}", line)?;
+                    continue;
+                }
+            };
+
+            if event["type"].as_str() != Some("test") {
+                continue;
+            }
+
+            let name = match event["name"].as_str() {
+                Some(name) => name,
+                None => continue,
+            };
+
+            match event["event"].as_str() {
+                Some("ok") => {
+                    results.entry(name.to_string()).or_default().1 = true;
+                }
+                Some("failed") | Some("timeout") => {
+                    let result = results.entry(name.to_string()).or_default();
+                    result.0 = true;
+                    result.1 = false;
+                }
+                _ => {}
+            }
+        }
+
+        let mut summary = Self::default();
+
+        for (name, (ever_failed, passed)) in results {
+            match (ever_failed, passed) {
+                (false, true) => summary.passed += 1,
+                (true, true) => summary.flaky.push(name),
+                (_, false) => summary.failed.push(name),
+            }
+        }
+
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> Summary {
+        Summary::parse(input.as_bytes(), std::io::sink()).unwrap()
+    }
+
+    #[test]
+    fn counts_passes_and_failures() {
+        let summary = parse(
+            r#"{"type":"suite","event":"started","test_count":3}
+{"type":"test","event":"started","name":"a::passes"}
+{"type":"test","event":"ok","name":"a::passes"}
+{"type":"test","event":"failed","name":"a::fails"}
+{"type":"test","event":"timeout","name":"a::hangs"}
+{"type":"suite","event":"failed","passed":1,"failed":2}"#,
+        );
+
+        assert_eq!(summary.passed, 1);
+        assert_eq!(summary.failed, vec!["a::fails", "a::hangs"]);
+        assert!(summary.flaky.is_empty());
+    }
+
+    #[test]
+    fn a_pass_after_a_failure_is_flaky() {
+        let summary = parse(
+            r#"{"type":"test","event":"failed","name":"a::retried"}
+{"type":"test","event":"ok","name":"a::retried"}"#,
+        );
+
+        assert_eq!(summary.passed, 0);
+        assert!(summary.failed.is_empty());
+        assert_eq!(summary.flaky, vec!["a::retried"]);
+    }
+
+    #[test]
+    fn a_failure_after_a_pass_is_a_failure() {
+        let summary = parse(
+            r#"{"type":"test","event":"ok","name":"a::regressed"}
+{"type":"test","event":"failed","name":"a::regressed"}"#,
+        );
+
+        assert_eq!(summary.failed, vec!["a::regressed"]);
+    }
+
+    #[test]
+    fn ignores_malformed_and_unrelated_lines() {
+        let summary = parse(
+            r#"   Compiling spirit v0.1.0
+{"type":"test","event":"ok"
+{"type":"test","event":"ok"}
+{"type":"test","event":"ok","name":7}
+{"type":"bench","event":"ok","name":"b::bench"}
+[1, 2, 3]
+
+{"type":"test","event":"ok","name":"a::passes"}"#,
+        );
+
+        assert_eq!(summary.passed, 1);
+        assert!(summary.failed.is_empty());
+        assert!(summary.flaky.is_empty());
+    }
+
+    #[test]
+    fn passes_through_other_output() {
+        let mut out = Vec::new();
+        Summary::parse(
+            r#"   Compiling spirit v0.1.0
+{"type":"test","event":"ok","name":"a::passes"}
+warning: unused variable"#
+                .as_bytes(),
+            &mut out,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "   Compiling spirit v0.1.0\nwarning: unused variable\n"
+        );
+    }
+
+    #[test]
+    fn empty_input_is_an_empty_summary() {
+        let summary = parse("");
+
+        assert_eq!(summary.passed, 0);
+        assert!(summary.failed.is_empty());
+        assert!(summary.flaky.is_empty());
+    }
+}
@@ -0,0 +1,267 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::fmt;
+use std::process::Stdio;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use serde::Deserialize;
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::{
+    events,
+    settings::{NotifierKind, NotifierSetting, Settings},
+};
+
+/// Something worth telling someone about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Event {
+    CheckFailed,
+    CheckPassed,
+    DeviceOffline,
+    DeviceOnline,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::CheckFailed => "check-failed",
+            Self::CheckPassed => "check-passed",
+            Self::DeviceOffline => "device-offline",
+            Self::DeviceOnline => "device-online",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Notification {
+    pub event: Event,
+    pub subject: String,
+    pub message: String,
+}
+
+impl Notification {
+    pub fn new(event: Event, subject: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            event,
+            subject: subject.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// A way of delivering notifications.
+pub trait Notifier: Send + Sync {
+    fn notify<'a>(&'a self, note: &'a Notification) -> BoxFuture<'a, Result<()>>;
+}
+
+/// How long a webhook gets to connect, and to answer at all. A check waits
+/// on its notifiers, so a dead endpoint mustn't hold up the build.
+const WEBHOOK_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A desktop notification via notify-send, or osascript on macOS.
+pub struct Desktop;
+
+impl Notifier for Desktop {
+    fn notify<'a>(&'a self, note: &'a Notification) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let status = if cfg!(target_os = "macos") {
+                let script = format!(
+                    "display notification {:?} with title {:?}",
+                    note.message, note.subject
+                );
+                Command::new("osascript")
+                    .args(["-e", &script])
+                    .status()
+                    .await
+            } else {
+                Command::new("notify-send")
+                    .args(["--app-name", "spirit", &note.subject, &note.message])
+                    .status()
+                    .await
+            }
+            .context("Could not start desktop notifier")?;
+
+            if !status.success() {
+                bail!("Desktop notifier exited with {}", status);
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// A json `POST` of the event, subject and message to `url`.
+pub struct Webhook {
+    url: String,
+    http: reqwest::Client,
+}
+
+impl Notifier for Webhook {
+    fn notify<'a>(&'a self, note: &'a Notification) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let body = serde_json::json!({
+                "event": note.event.to_string(),
+                "subject": note.subject,
+                "message": note.message,
+            });
+
+            self.http
+                .post(&self.url)
+                .header("Content-Type", "application/json")
+                .body(body.to_string())
+                .send()
+                .await?
+                .error_for_status()?;
+
+            Ok(())
+        })
+    }
+}
+
+/// A plain text email handed to the local `sendmail`.
+pub struct Email {
+    to: String,
+}
+
+impl Notifier for Email {
+    fn notify<'a>(&'a self, note: &'a Notification) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut child = Command::new("sendmail")
+                .arg("-t")
+                .stdin(Stdio::piped())
+                .spawn()
+                .context("Could not start sendmail")?;
+
+            let mail = format!(
+                "To: {}\nSubject: [spirit] {}\n\n{}\n",
+                header_value(&self.to),
+                header_value(&note.subject),
+                note.message
+            );
+
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(mail.as_bytes()).await?;
+            }
+
+            let status = child.wait().await?;
+            if !status.success() {
+                bail!("sendmail exited with {}", status);
+            }
+
+            Ok(())
+        })
+    }
+}
+
+/// `raw` with line breaks flattened, so it can't end its header and start
+/// another.
+fn header_value(raw: &str) -> String {
+    raw.replace(['\r', '\n'], " ")
+}
+
+/// A shell command, run with `sh -c` and given the notification as
+/// SPIRIT_EVENT, SPIRIT_SUBJECT and SPIRIT_MESSAGE.
+pub struct Exec {
+    command: String,
+}
+
+impl Notifier for Exec {
+    fn notify<'a>(&'a self, note: &'a Notification) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let status = Command::new("sh")
+                .arg("-c")
+                .arg(&self.command)
+                .env("SPIRIT_EVENT", note.event.to_string())
+                .env("SPIRIT_SUBJECT", &note.subject)
+                .env("SPIRIT_MESSAGE", &note.message)
+                .status()
+                .await
+                .with_context(|| format!("Could not run '{}'", self.command))?;
+
+            if !status.success() {
+                bail!("'{}' exited with {}", self.command, status);
+            }
+
+            Ok(())
+        })
+    }
+}
+
+struct Route {
+    name: String,
+    events: Option<Vec<Event>>,
+    notifier: Box<dyn Notifier>,
+}
+
+/// The configured `[[notifiers]]`, each with the events routed to it.
+pub struct Notifiers {
+    routes: Vec<Route>,
+}
+
+impl Notifiers {
+    pub fn from_settings(settings: &Settings) -> Result<Self> {
+        let mut routes = Vec::new();
+
+        for setting in settings.notifiers.iter().flatten() {
+            routes.push(Route {
+                name: setting.name.clone(),
+                events: setting.events.clone(),
+                notifier: build(setting)
+                    .with_context(|| format!("Invalid notifier '{}'", setting.name))?,
+            });
+        }
+
+        Ok(Self { routes })
+    }
+
+    /// Send `note` to every notifier routed its event.
+    ///
+    /// Notifications are best effort, so failures are reported and logged
+    /// rather than returned.
+    pub async fn notify(&self, note: Notification) {
+        for route in self.routes.iter() {
+            let wanted = route
+                .events
+                .as_ref()
+                .map(|events| events.contains(&note.event))
+                .unwrap_or(true);
+
+            if !wanted {
+                continue;
+            }
+
+            match route.notifier.notify(&note).await {
+                Ok(()) => events::record("notify", format!("{} {}", route.name, note.event)),
+                Err(e) => {
+                    events::record("error", format!("notifier {}: {:#}", route.name, e));
+                    eprintln!("Could not notify {}: {:#}", route.name, e);
+                }
+            }
+        }
+    }
+}
+
+fn build(setting: &NotifierSetting) -> Result<Box<dyn Notifier>> {
+    let missing = |field: &str| anyhow!("{} notifiers need `{}`", setting.kind, field);
+
+    Ok(match setting.kind {
+        NotifierKind::Desktop => Box::new(Desktop),
+        NotifierKind::Webhook => Box::new(Webhook {
+            url: setting.url.clone().ok_or_else(|| missing("url"))?,
+            http: reqwest::Client::builder()
+                .connect_timeout(WEBHOOK_CONNECT_TIMEOUT)
+                .timeout(WEBHOOK_TIMEOUT)
+                .build()?,
+        }),
+        NotifierKind::Email => Box::new(Email {
+            to: setting.to.clone().ok_or_else(|| missing("to"))?,
+        }),
+        NotifierKind::Command => Box::new(Exec {
+            command: setting.command.clone().ok_or_else(|| missing("command"))?,
+        }),
+    })
+}
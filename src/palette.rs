@@ -0,0 +1,83 @@
+use anyhow::{anyhow, Result};
+
+use crate::{color::Rgb, settings::PaletteSetting};
+
+const BUILTIN: &[(&str, &[&str])] = &[
+    (
+        "forest",
+        &["#1b4d2b", "#2e6b34", "#4f7942", "#6b8e23", "#8f9779"],
+    ),
+    (
+        "ocean",
+        &["#003f5c", "#0a6c8c", "#1d8fb3", "#2ec4b6", "#4a6fa5"],
+    ),
+    (
+        "sunset",
+        &["#ff6b35", "#f7931e", "#c1440e", "#9e2a2b", "#6a2c70"],
+    ),
+    ("ember", &["#ff4500", "#e25822", "#b22222", "#ff8c00"]),
+];
+
+/// A named set of colors to choose from.
+#[derive(Debug, Clone)]
+pub struct Palette {
+    pub name: String,
+    pub colors: Vec<Rgb>,
+}
+
+impl Palette {
+    /// Find a palette by name, preferring those defined in config over the
+    /// built in ones.
+    pub fn find(configured: Option<&[PaletteSetting]>, name: &str) -> Result<Self> {
+        if let Some(setting) = configured.and_then(|p| p.iter().find(|p| p.name == name)) {
+            return Self::from_setting(setting);
+        }
+
+        let (_, colors) = BUILTIN
+            .iter()
+            .find(|(n, _)| *n == name)
+            .ok_or_else(|| anyhow!("Unknown palette '{}'", name))?;
+
+        Ok(Self {
+            name: name.to_string(),
+            colors: colors
+                .iter()
+                .map(|c| Rgb::parse(c))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Every valid configured palette followed by the built in ones.
+    pub fn all(configured: Option<&[PaletteSetting]>) -> Vec<Self> {
+        let mut all: Vec<Self> = configured
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|p| Self::from_setting(p).ok())
+            .collect();
+
+        for (name, _) in BUILTIN {
+            if let Ok(palette) = Self::find(None, name) {
+                all.push(palette);
+            }
+        }
+
+        all
+    }
+
+    pub fn from_setting(setting: &PaletteSetting) -> Result<Self> {
+        let colors: Vec<Rgb> = setting
+            .colors
+            .iter()
+            .map(|c| Rgb::parse(c))
+            .collect::<Result<_>>()?;
+
+        if colors.is_empty() {
+            return Err(anyhow!("Palette '{}' has no colors", setting.name));
+        }
+
+        Ok(Self {
+            name: setting.name.clone(),
+            colors,
+        })
+    }
+}
@@ -0,0 +1,147 @@
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use govee_rs::models::{Device, Devices};
+use serde::{Deserialize, Serialize};
+
+use crate::{capability, client::Client, color::Rgb, state::DeviceSnapshot};
+
+/// A named set of device snapshots, keyed by device name.
+pub type Scene = BTreeMap<String, DeviceSnapshot>;
+
+/// The contents of the scenes file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Scenes {
+    #[serde(default)]
+    pub scenes: BTreeMap<String, Scene>,
+}
+
+impl Scenes {
+    /// The default scenes file, `spirit-scenes.toml` in the home directory.
+    pub fn default_path() -> Result<PathBuf> {
+        match dirs::home_dir() {
+            Some(home) => Ok(home.join(Path::new(OsStr::new("spirit-scenes.toml")))),
+            None => bail!("Could not determine home directory for scenes file"),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let raw = fs::read_to_string(path)
+            .with_context(|| format!("Could not read scenes file {}", path.display()))?;
+
+        toml::from_str(&raw)
+            .with_context(|| format!("Could not parse scenes file {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let raw = toml::to_string_pretty(self)?;
+
+        fs::write(path, raw)
+            .with_context(|| format!("Could not write scenes file {}", path.display()))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Scene> {
+        self.scenes.get(name)
+    }
+
+    pub fn insert(&mut self, name: &str, scene: Scene) {
+        self.scenes.insert(name.to_string(), scene);
+    }
+}
+
+/// Where one device starts and ends up during a crossfade.
+struct Fade<'a> {
+    device: &'a Device,
+    from: (Rgb, u8),
+    to: (Rgb, u8),
+}
+
+/// Blend the selected devices in `scene` from their current state to the
+/// scene's over `duration`, in `steps` increments.
+///
+/// Devices the scene turns off fade down to minimum brightness first, and
+/// devices that are currently off fade up from it. Every device gets the
+/// exact scene state at the end. Switches just change at the end.
+pub async fn crossfade(
+    client: &Client,
+    devices: &Devices,
+    scene: &Scene,
+    duration: Duration,
+    steps: u32,
+) -> Result<()> {
+    let mut fades = Vec::new();
+
+    for device in devices.iter() {
+        let target = match scene.get(&device.name) {
+            Some(target) => target,
+            None => continue,
+        };
+
+        if !capability::has_color(device) {
+            continue;
+        }
+
+        let current = client.snapshot(device).await?;
+        let parse = |color: Option<&String>| color.map(|c| Rgb::parse(c)).transpose();
+
+        let current_color = parse(current.color.as_ref())?;
+        let target_color = parse(target.color.as_ref())?;
+
+        let from_color = current_color
+            .or(target_color)
+            .unwrap_or_else(|| Rgb::new(255, 255, 255));
+        let to_color = target_color.unwrap_or(from_color);
+
+        let from_brightness = match current.power {
+            Some(true) => current.brightness.unwrap_or(100),
+            _ => 1,
+        };
+        let to_brightness = match target.power {
+            Some(false) => 1,
+            _ => target.brightness.unwrap_or(from_brightness),
+        };
+
+        fades.push(Fade {
+            device,
+            from: (from_color, from_brightness),
+            to: (to_color, to_brightness),
+        });
+    }
+
+    let steps = steps.max(1);
+    let interval = duration / steps;
+
+    for step in 1..steps {
+        tokio::time::sleep(interval).await;
+        let t = f64::from(step) / f64::from(steps);
+
+        for fade in fades.iter() {
+            let color = fade.from.0.lerp(fade.to.0, t);
+            let brightness =
+                f64::from(fade.from.1) + (f64::from(fade.to.1) - f64::from(fade.from.1)) * t;
+
+            client.color_raw(fade.device, color.to_color()?).await?;
+            client
+                .brightness(fade.device, brightness.round() as u8)
+                .await?;
+        }
+    }
+
+    tokio::time::sleep(interval).await;
+
+    for device in devices.iter() {
+        if let Some(target) = scene.get(&device.name) {
+            target.apply(client, device).await?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,312 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use govee_rs::models::{Device, Devices, PowerState};
+use serde::Deserialize;
+use tokio::time::Instant;
+
+use crate::{capability, client::Client, color::Rgb};
+
+/// One change in a sequence.
+///
+/// `at` is seconds from the start. Without `device` the change applies to
+/// every selected device.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Keyframe {
+    pub at: f64,
+    pub device: Option<String>,
+    pub color: Option<String>,
+    pub brightness: Option<u8>,
+    pub power: Option<bool>,
+}
+
+/// A validated keyframe, ready to send.
+#[derive(Debug, Clone)]
+struct Step {
+    at: Duration,
+    device: Option<String>,
+    color: Option<Rgb>,
+    brightness: Option<u8>,
+    power: Option<bool>,
+}
+
+/// Timestamped color and brightness changes, played back against the
+/// selected devices.
+#[derive(Debug)]
+pub struct Sequence {
+    steps: Vec<Step>,
+}
+
+impl Sequence {
+    /// Load a sequence from a json array of keyframes, or a csv file with an
+    /// `at,device,color,brightness,power` header where any column but `at`
+    /// may be left empty.
+    pub fn load(path: &Path) -> Result<Self> {
+        let raw = fs::read_to_string(path)
+            .with_context(|| format!("Could not read sequence {}", path.display()))?;
+
+        let keyframes = match path.extension().and_then(|e| e.to_str()) {
+            Some("csv") => parse_csv(&raw),
+            _ => serde_json::from_str(&raw).map_err(anyhow::Error::from),
+        }
+        .with_context(|| format!("Could not parse sequence {}", path.display()))?;
+
+        Self::from_keyframes(keyframes)
+    }
+
+    pub fn from_keyframes(keyframes: Vec<Keyframe>) -> Result<Self> {
+        if keyframes.is_empty() {
+            bail!("Sequence has no keyframes");
+        }
+
+        let mut steps = Vec::with_capacity(keyframes.len());
+
+        for (i, keyframe) in keyframes.into_iter().enumerate() {
+            if !keyframe.at.is_finite() || keyframe.at < 0.0 {
+                bail!("Keyframe {} has an invalid time {}", i + 1, keyframe.at);
+            }
+
+            steps.push(Step {
+                at: Duration::from_secs_f64(keyframe.at),
+                device: keyframe.device,
+                color: keyframe
+                    .color
+                    .as_deref()
+                    .map(Rgb::parse)
+                    .transpose()
+                    .with_context(|| format!("Keyframe {} has an invalid color", i + 1))?,
+                brightness: keyframe.brightness,
+                power: keyframe.power,
+            });
+        }
+
+        // stable, so keyframes at the same time keep their file order
+        steps.sort_by(|a, b| a.at.cmp(&b.at));
+
+        Ok(Self { steps })
+    }
+
+    /// The names of devices the sequence mentions that aren't in `devices`.
+    pub fn unknown_devices(&self, devices: &Devices) -> Vec<String> {
+        let mut unknown: Vec<String> = self
+            .steps
+            .iter()
+            .filter_map(|s| s.device.clone())
+            .filter(|name| !devices.iter().any(|d| d.name == *name))
+            .collect();
+
+        unknown.sort();
+        unknown.dedup();
+        unknown
+    }
+
+    /// Play the sequence once, sending the changes for each keyframe
+    /// concurrently.
+    ///
+    /// Keyframes are timed from the start rather than from each other, so a
+    /// slow request delays the next change but doesn't shift the rest.
+    pub async fn play(&self, client: &Client, devices: &Devices) -> Result<()> {
+        let start = Instant::now();
+
+        for step in self.steps.iter() {
+            tokio::time::sleep_until(start + step.at).await;
+
+            let targets = devices.iter().filter(|d| match step.device {
+                Some(ref name) => d.name == *name,
+                None => true,
+            });
+
+            let results = client
+                .each(targets, |client, device| {
+                    let step = step.clone();
+                    async move { apply(&client, &device, &step).await }
+                })
+                .await;
+
+            for (_, res) in results {
+                res?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Send one step to `device`. Switches only follow `power`.
+async fn apply(client: &Client, device: &Device, step: &Step) -> Result<()> {
+    if step.power == Some(false) {
+        return client.turn(device, PowerState::Off).await;
+    }
+
+    if !capability::is_light(device) {
+        if step.power == Some(true) {
+            client.turn(device, PowerState::On).await?;
+        }
+        return Ok(());
+    }
+
+    match step.color {
+        Some(color) if capability::has_color(device) => {
+            client.color(device, color.to_color()?).await?
+        }
+        Some(_) => client.turn(device, PowerState::On).await?,
+        None if step.power == Some(true) => client.turn(device, PowerState::On).await?,
+        None => {}
+    }
+
+    if let Some(brightness) = step.brightness {
+        client.brightness(device, brightness).await?;
+    }
+
+    Ok(())
+}
+
+pub fn parse_csv(raw: &str) -> Result<Vec<Keyframe>> {
+    let mut lines = raw.lines().filter(|l| !l.trim().is_empty());
+
+    let header: Vec<String> = match lines.next() {
+        Some(header) => header.split(',').map(|h| h.trim().to_lowercase()).collect(),
+        None => return Ok(Vec::new()),
+    };
+
+    let column = |name: &str| header.iter().position(|h| h == name);
+    let at_col = match column("at") {
+        Some(at_col) => at_col,
+        None => bail!("The csv header needs an 'at' column"),
+    };
+    let (device_col, color_col, brightness_col, power_col) = (
+        column("device"),
+        column("color"),
+        column("brightness"),
+        column("power"),
+    );
+
+    let mut keyframes = Vec::new();
+
+    for (i, line) in lines.enumerate() {
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        let field = |col: Option<usize>| {
+            col.and_then(|c| fields.get(c))
+                .copied()
+                .filter(|f| !f.is_empty())
+        };
+        let row = i + 2;
+
+        keyframes.push(Keyframe {
+            at: field(Some(at_col))
+                .unwrap_or_default()
+                .parse()
+                .with_context(|| format!("Row {} has an invalid time", row))?,
+            device: field(device_col).map(String::from),
+            color: field(color_col).map(String::from),
+            brightness: field(brightness_col)
+                .map(|b| b.parse())
+                .transpose()
+                .with_context(|| format!("Row {} has an invalid brightness", row))?,
+            power: field(power_col)
+                .map(|p| match p {
+                    "on" | "true" | "1" => Ok(true),
+                    "off" | "false" | "0" => Ok(false),
+                    other => Err(anyhow::anyhow!("'{}' is not on or off", other)),
+                })
+                .transpose()
+                .with_context(|| format!("Row {} has an invalid power", row))?,
+        });
+    }
+
+    Ok(keyframes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframe(at: f64) -> Keyframe {
+        Keyframe {
+            at,
+            device: None,
+            color: None,
+            brightness: None,
+            power: None,
+        }
+    }
+
+    #[test]
+    fn parses_csv_with_empty_columns() {
+        let keyframes = parse_csv(
+            "at, device, color, brightness, power\n\
+             0, , #ff0000, , on\n\
+             \n\
+             1.5, desk, , 40,\n\
+             2, , , , off\n",
+        )
+        .unwrap();
+
+        assert_eq!(keyframes.len(), 3);
+        assert_eq!(keyframes[0].color.as_deref(), Some("#ff0000"));
+        assert_eq!(keyframes[0].power, Some(true));
+        assert_eq!(keyframes[1].at, 1.5);
+        assert_eq!(keyframes[1].device.as_deref(), Some("desk"));
+        assert_eq!(keyframes[1].brightness, Some(40));
+        assert_eq!(keyframes[1].power, None);
+        assert_eq!(keyframes[2].power, Some(false));
+    }
+
+    #[test]
+    fn csv_columns_can_be_in_any_order() {
+        let keyframes = parse_csv("Color,AT\n#00ff00,3\n").unwrap();
+
+        assert_eq!(keyframes[0].at, 3.0);
+        assert_eq!(keyframes[0].color.as_deref(), Some("#00ff00"));
+    }
+
+    #[test]
+    fn rejects_malformed_csv() {
+        assert!(parse_csv("device,color\ndesk,#ff0000\n").is_err());
+        assert!(parse_csv("at,color\nsoon,#ff0000\n").is_err());
+        assert!(parse_csv("at,color\n,#ff0000\n").is_err());
+        assert!(parse_csv("at,brightness\n1,bright\n").is_err());
+        assert!(parse_csv("at,brightness\n1,300\n").is_err());
+        assert!(parse_csv("at,power\n1,maybe\n").is_err());
+    }
+
+    #[test]
+    fn empty_csv_has_no_keyframes() {
+        assert!(parse_csv("").unwrap().is_empty());
+        assert!(Sequence::from_keyframes(parse_csv("at\n").unwrap()).is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_keyframes() {
+        assert!(Sequence::from_keyframes(Vec::new()).is_err());
+        assert!(Sequence::from_keyframes(vec![keyframe(-1.0)]).is_err());
+        assert!(Sequence::from_keyframes(vec![keyframe(f64::NAN)]).is_err());
+        assert!(Sequence::from_keyframes(vec![keyframe(f64::INFINITY)]).is_err());
+
+        let mut bad_color = keyframe(0.0);
+        bad_color.color = Some("red".to_string());
+        assert!(Sequence::from_keyframes(vec![bad_color]).is_err());
+    }
+
+    #[test]
+    fn steps_are_sorted_keeping_file_order_for_ties() {
+        let mut first = keyframe(1.0);
+        first.device = Some("first".to_string());
+        let mut second = keyframe(1.0);
+        second.device = Some("second".to_string());
+
+        let sequence = Sequence::from_keyframes(vec![keyframe(2.0), first, second]).unwrap();
+        let order: Vec<_> = sequence
+            .steps
+            .iter()
+            .map(|s| (s.at.as_secs_f64(), s.device.as_deref()))
+            .collect();
+
+        assert_eq!(
+            order,
+            vec![(1.0, Some("first")), (1.0, Some("second")), (2.0, None)]
+        );
+    }
+}
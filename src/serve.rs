@@ -0,0 +1,587 @@
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use futures::{stream::FuturesUnordered, StreamExt};
+use govee_rs::models::Devices;
+use ipnet::IpNet;
+use serde::Serialize;
+use subtle::ConstantTimeEq;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Take},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+use crate::{
+    action::Action,
+    cache::StateCache,
+    client::Client,
+    events,
+    settings::{Scope, Settings},
+    state::DeviceSnapshot,
+};
+
+/// How long a client gets to send its request line and headers.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The most request line and header bytes read before giving up on a request.
+const MAX_REQUEST_BYTES: u64 = 16 * 1024;
+
+/// How many connections are handled at once. Others wait to be accepted.
+const MAX_CONNECTIONS: usize = 32;
+
+/// How long to wait after failing to accept a connection before trying again.
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+struct Button {
+    name: String,
+    action: Action,
+    devices: Devices,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    token: Option<String>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn text(status: &'static str, body: String) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            body,
+        }
+    }
+}
+
+/// Who may use a listening mode: the `[serve]` tokens and allowed
+/// addresses, shared by everything that accepts connections or frames.
+pub struct Access {
+    tokens: Vec<(String, Scope)>,
+    allowed: Vec<IpNet>,
+}
+
+impl Access {
+    pub fn from_settings(settings: &Settings) -> Result<Self> {
+        let serve_settings = settings.serve.clone().unwrap_or_default();
+
+        let tokens = serve_settings
+            .tokens
+            .as_ref()
+            .map(|tokens| tokens.iter().map(|t| (t.token.clone(), t.scope)).collect())
+            .unwrap_or_default();
+
+        let mut allowed = Vec::new();
+        for entry in serve_settings.allowed_ips.iter().flatten() {
+            allowed.push(parse_net(entry)?);
+        }
+
+        Ok(Self { tokens, allowed })
+    }
+
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        // a dual-stack listener sees IPv4 clients as IPv4-mapped IPv6
+        // addresses, which should match the IPv4 rules
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            IpAddr::V4(_) => ip,
+        };
+
+        self.allowed.is_empty() || self.allowed.iter().any(|net| net.contains(&ip))
+    }
+
+    /// The scope granted to `token`, or `None` if it grants nothing.
+    pub fn scope(&self, token: Option<&str>) -> Option<Scope> {
+        if self.tokens.is_empty() {
+            return Some(Scope::Control);
+        }
+
+        // compare in constant time, and against every token, so response
+        // timing gives away nothing about how close a guess was
+        let token = token?;
+        let mut granted = None;
+        for (candidate, scope) in self.tokens.iter() {
+            if bool::from(candidate.as_bytes().ct_eq(token.as_bytes())) {
+                granted = Some(*scope);
+            }
+        }
+
+        granted
+    }
+}
+
+struct Server<'a> {
+    client: &'a Client,
+    devices: &'a Devices,
+    buttons: Vec<Button>,
+    access: Access,
+    /// How old a cached device state may be before `/state` refreshes it.
+    ttl: Duration,
+}
+
+/// A device's entry in the `/state` response.
+#[derive(Serialize)]
+struct DeviceState {
+    #[serde(flatten)]
+    snapshot: DeviceSnapshot,
+    /// Seconds since the unix epoch at which the state last changed, if
+    /// that's been seen.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    changed: Option<u64>,
+}
+
+/// Serve the configured buttons and device state over plain http on `addr`
+/// until interrupted.
+///
+/// Each button is triggered by a `GET` or `POST` to `/press/<name>`, which
+/// keeps the urls simple enough to paste into Stream Deck style "open url"
+/// actions. `GET /state` returns the state of the selected devices as json,
+/// with when each last changed. It's read from the state cache, which `spirit
+/// watch` keeps fresh if it's running, and only devices missing from it or
+/// cached longer than `ttl` are fetched.
+///
+/// If any tokens are configured every request must carry one, either as a
+/// bearer token or a percent-encoded `token` query parameter, and pressing
+/// buttons requires a token with the control scope. If `allowed_ips` is
+/// configured, connections from other addresses are refused before anything
+/// is read.
+/// Connections are handled concurrently, and one that is slow to send its
+/// request or sends oversized headers is dropped.
+pub async fn serve(
+    client: &Client,
+    settings: &Settings,
+    devices: &Devices,
+    addr: SocketAddr,
+    ttl: Duration,
+) -> Result<()> {
+    let mut buttons = Vec::new();
+
+    for button in settings.buttons.iter().flatten() {
+        let action = Action::from_setting(&button.action)
+            .with_context(|| format!("Invalid action for button '{}'", button.name))?;
+
+        let mut targets = devices.clone();
+        if let Some(ref names) = button.devices {
+            targets.devices.retain(|d| names.contains(&d.name));
+        }
+
+        buttons.push(Button {
+            name: button.name.clone(),
+            action,
+            devices: targets,
+        });
+    }
+
+    let server = Server {
+        client,
+        devices,
+        buttons,
+        access: Access::from_settings(settings)?,
+        ttl,
+    };
+
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Could not listen on {}", addr))?;
+
+    events::record("serve", format!("listening on {}", addr));
+    println!("Listening on http://{}", addr);
+    println!("  http://{}/state", addr);
+    for button in server.buttons.iter() {
+        println!("  http://{}/press/{}", addr, button.name);
+    }
+
+    let mut connections = FuturesUnordered::new();
+
+    loop {
+        tokio::select! {
+            (mut stream, peer) = accept(|| listener.accept()), if connections.len() < MAX_CONNECTIONS => {
+
+                if !server.access.allows(peer.ip()) {
+                    events::record("refused", format!("connection from {}", peer));
+                    let _ = stream
+                        .write_all(
+                            b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                        )
+                        .await;
+                    continue;
+                }
+
+                let server = &server;
+                connections.push(async move {
+                    if let Err(e) = server.handle(stream, peer).await {
+                        events::record("error", format!("request from {} failed: {:#}", peer, e));
+                        eprintln!("Request failed: {:#}", e);
+                    }
+                });
+            }
+            Some(()) = connections.next(), if !connections.is_empty() => {}
+        }
+    }
+}
+
+/// Take the next connection from `accept`.
+///
+/// A failure to accept (a connection reset before it was taken, or running
+/// out of file descriptors) is about that connection or a passing condition,
+/// not the listener, so it's logged and the next one is waited for.
+async fn accept<F, Fut, T>(mut accept: F) -> T
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    loop {
+        match accept().await {
+            Ok(accepted) => return accepted,
+            Err(e) => {
+                eprintln!("Could not accept a connection: {}", e);
+                tokio::time::sleep(ACCEPT_RETRY).await;
+            }
+        }
+    }
+}
+
+impl<'a> Server<'a> {
+    async fn handle(&self, mut stream: TcpStream, peer: SocketAddr) -> Result<()> {
+        let request = timeout(REQUEST_TIMEOUT, read_request(&mut stream))
+            .await
+            .context("Timed out reading request")??;
+        let response = self.respond(&request).await;
+
+        events::record(
+            "request",
+            format!(
+                "{} {} {} -> {}",
+                peer, request.method, request.path, response.status
+            ),
+        );
+
+        let raw = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            response.status,
+            response.content_type,
+            response.body.len(),
+            response.body
+        );
+
+        stream.write_all(raw.as_bytes()).await?;
+        stream.shutdown().await?;
+
+        Ok(())
+    }
+
+    async fn respond(&self, request: &Request) -> Response {
+        let scope = match self.access.scope(request.token.as_deref()) {
+            Some(scope) => scope,
+            None => return Response::text("401 Unauthorized", "missing or unknown token\n".into()),
+        };
+
+        let press = request.path.strip_prefix("/press/");
+
+        match (request.method.as_str(), request.path.as_str(), press) {
+            ("GET", "/state", _) => self.state().await,
+            ("GET", _, Some(name)) | ("POST", _, Some(name)) => {
+                if scope != Scope::Control {
+                    return Response::text("403 Forbidden", "token is read-only\n".into());
+                }
+
+                self.press(name).await
+            }
+            _ => Response::text("404 Not Found", "not found\n".into()),
+        }
+    }
+
+    async fn press(&self, name: &str) -> Response {
+        match self.buttons.iter().find(|b| b.name == name) {
+            Some(button) => match button.action.run(self.client, &button.devices).await {
+                Ok(()) => Response::text("200 OK", format!("{}: ok\n", name)),
+                Err(e) => Response::text("502 Bad Gateway", format!("{}: {:#}\n", name, e)),
+            },
+            None => Response::text("404 Not Found", format!("no button named '{}'\n", name)),
+        }
+    }
+
+    async fn state(&self) -> Response {
+        let res = async {
+            let mut cache = StateCache::load()?;
+            let snapshots = cache.refresh(self.client, self.devices, self.ttl).await?;
+            Ok::<_, anyhow::Error>((cache, snapshots))
+        }
+        .await;
+
+        let (cache, snapshots) = match res {
+            Ok(res) => res,
+            Err(e) => return Response::text("502 Bad Gateway", format!("{:#}\n", e)),
+        };
+
+        let states: BTreeMap<&str, DeviceState> = self
+            .devices
+            .iter()
+            .zip(snapshots)
+            .map(|(device, snapshot)| {
+                let changed = cache.devices.get(&device.name).and_then(|c| c.changed);
+                (device.name.as_str(), DeviceState { snapshot, changed })
+            })
+            .collect();
+
+        match serde_json::to_string(&states) {
+            Ok(body) => Response {
+                status: "200 OK",
+                content_type: "application/json",
+                body,
+            },
+            Err(e) => Response::text("500 Internal Server Error", format!("{}\n", e)),
+        }
+    }
+}
+
+fn parse_net(entry: &str) -> Result<IpNet> {
+    if entry.contains('/') {
+        entry
+            .parse()
+            .with_context(|| format!("Invalid CIDR range '{}' in allowed_ips", entry))
+    } else {
+        let ip: IpAddr = entry
+            .parse()
+            .with_context(|| format!("Invalid address '{}' in allowed_ips", entry))?;
+        Ok(IpNet::from(ip))
+    }
+}
+
+/// Read one line, failing if it was cut short by the size limit.
+async fn read_limited_line(
+    reader: &mut BufReader<Take<&mut TcpStream>>,
+    line: &mut String,
+) -> Result<usize> {
+    let read = reader.read_line(line).await?;
+
+    if !line.ends_with('\n') && reader.get_ref().limit() == 0 {
+        bail!("Request headers larger than {} bytes", MAX_REQUEST_BYTES);
+    }
+
+    Ok(read)
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<Request> {
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_BYTES));
+
+    let mut request_line = String::new();
+    read_limited_line(&mut reader, &mut request_line).await?;
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let target = parts.next().unwrap_or_default();
+
+    if method.is_empty() || target.is_empty() {
+        bail!("Malformed request line");
+    }
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (target, None),
+    };
+
+    let mut token = query.and_then(|q| {
+        q.split('&')
+            .find_map(|pair| pair.strip_prefix("token="))
+            .and_then(percent_decode)
+    });
+
+    loop {
+        let mut line = String::new();
+        if read_limited_line(&mut reader, &mut line).await? == 0 || line.trim().is_empty() {
+            break;
+        }
+
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("authorization") {
+                if let Some(bearer) = value.trim().strip_prefix("Bearer ") {
+                    token = Some(bearer.trim().to_string());
+                }
+            }
+        }
+    }
+
+    Ok(Request {
+        method,
+        path: path.to_string(),
+        token,
+    })
+}
+
+/// Decode `%XX` escapes in a query value, or `None` if they're malformed or
+/// don't decode to utf-8. A `+` is left alone, since tokens are typed into
+/// urls by hand more often than they're submitted from forms.
+fn percent_decode(raw: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(raw.len());
+    let mut rest = raw.as_bytes();
+
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access(allowed: &[&str]) -> Access {
+        Access {
+            tokens: Vec::new(),
+            allowed: allowed.iter().map(|a| parse_net(a).unwrap()).collect(),
+        }
+    }
+
+    fn ip(raw: &str) -> IpAddr {
+        raw.parse().unwrap()
+    }
+
+    #[test]
+    fn plain_addresses_match_only_themselves() {
+        let access = access(&["192.168.1.10", "::1"]);
+
+        assert!(access.allows(ip("192.168.1.10")));
+        assert!(!access.allows(ip("192.168.1.11")));
+        assert!(access.allows(ip("::1")));
+        assert!(!access.allows(ip("127.0.0.1")));
+    }
+
+    #[test]
+    fn cidr_ranges_include_their_edges() {
+        let access = access(&["10.0.0.0/30", "fd00::/127"]);
+
+        assert!(!access.allows(ip("9.255.255.255")));
+        assert!(access.allows(ip("10.0.0.0")));
+        assert!(access.allows(ip("10.0.0.3")));
+        assert!(!access.allows(ip("10.0.0.4")));
+        assert!(access.allows(ip("fd00::1")));
+        assert!(!access.allows(ip("fd00::2")));
+    }
+
+    #[test]
+    fn host_bits_in_a_range_are_ignored() {
+        let access = access(&["192.168.1.77/24"]);
+
+        assert!(access.allows(ip("192.168.1.0")));
+        assert!(access.allows(ip("192.168.1.255")));
+        assert!(!access.allows(ip("192.168.2.0")));
+    }
+
+    #[test]
+    fn wide_ranges() {
+        let everything = access(&["0.0.0.0/0"]);
+        assert!(everything.allows(ip("8.8.8.8")));
+        assert!(!everything.allows(ip("::1")));
+
+        let single = access(&["10.1.2.3/32"]);
+        assert!(single.allows(ip("10.1.2.3")));
+        assert!(!single.allows(ip("10.1.2.4")));
+    }
+
+    #[test]
+    fn ipv4_mapped_addresses_match_ipv4_rules() {
+        let access = access(&["192.168.1.0/24"]);
+
+        assert!(access.allows(ip("::ffff:192.168.1.10")));
+        assert!(!access.allows(ip("::ffff:192.168.2.10")));
+
+        // only the mapped form, not the deprecated compatible one
+        assert!(!access.allows(ip("::192.168.1.10")));
+    }
+
+    #[tokio::test]
+    async fn accept_errors_are_skipped() {
+        let mut attempts = vec![
+            Ok(3),
+            Err(io::Error::from(io::ErrorKind::ConnectionAborted)),
+            Err(io::Error::from(io::ErrorKind::Other)),
+        ];
+        let next = accept(|| {
+            let attempt = attempts.pop().unwrap();
+            async move { attempt }
+        })
+        .await;
+
+        assert_eq!(next, 3);
+        assert!(attempts.is_empty());
+    }
+
+    #[test]
+    fn no_allowlist_allows_everyone() {
+        assert!(access(&[]).allows(ip("203.0.113.9")));
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        for entry in [
+            "",
+            "localhost",
+            "192.168.1",
+            "192.168.1.256",
+            "10.0.0.0/33",
+            "10.0.0.0/",
+            "/24",
+            "fd00::/129",
+        ] {
+            assert!(parse_net(entry).is_err(), "{:?} should be rejected", entry);
+        }
+    }
+
+    #[test]
+    fn query_tokens_are_percent_decoded() {
+        assert_eq!(percent_decode("plain").as_deref(), Some("plain"));
+        assert_eq!(percent_decode("a%2Bb%3D%3d").as_deref(), Some("a+b=="));
+        assert_eq!(percent_decode("a+b").as_deref(), Some("a+b"));
+        assert_eq!(percent_decode("%C3%A9").as_deref(), Some("é"));
+        assert_eq!(percent_decode(""), Some(String::new()));
+
+        assert_eq!(percent_decode("%"), None);
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%+1"), None);
+        assert_eq!(percent_decode("%FF"), None);
+    }
+
+    #[test]
+    fn tokens_grant_their_scope() {
+        let tokens = Access {
+            tokens: vec![
+                ("reader".to_string(), Scope::Read),
+                ("writer".to_string(), Scope::Control),
+            ],
+            allowed: Vec::new(),
+        };
+
+        assert_eq!(tokens.scope(Some("reader")), Some(Scope::Read));
+        assert_eq!(tokens.scope(Some("writer")), Some(Scope::Control));
+        assert_eq!(tokens.scope(Some("write")), None);
+        assert_eq!(tokens.scope(Some("")), None);
+        assert_eq!(tokens.scope(None), None);
+
+        // without tokens anyone may do anything
+        assert_eq!(access(&[]).scope(None), Some(Scope::Control));
+    }
+}
@@ -0,0 +1,277 @@
+use anyhow::{bail, Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A long-running spirit command registered with the platform's service
+/// manager: a systemd user unit on Linux or a launchd agent on macOS.
+pub struct Service {
+    label: String,
+}
+
+impl Service {
+    pub fn new(name: &str) -> Self {
+        Self {
+            label: format!("spirit-{}", name),
+        }
+    }
+
+    /// Where the unit or agent definition lives.
+    pub fn path(&self) -> Result<PathBuf> {
+        if cfg!(target_os = "macos") {
+            match dirs::home_dir() {
+                Some(home) => Ok(home
+                    .join("Library/LaunchAgents")
+                    .join(format!("com.{}.plist", self.label))),
+                None => bail!("Could not determine home directory for launch agent"),
+            }
+        } else if cfg!(target_os = "linux") {
+            match dirs::config_dir() {
+                Some(config) => Ok(config
+                    .join("systemd/user")
+                    .join(format!("{}.service", self.label))),
+                None => bail!("Could not determine config directory for systemd unit"),
+            }
+        } else {
+            bail!("Services are only supported with systemd on Linux and launchd on macOS")
+        }
+    }
+
+    /// The file holding the api key for a systemd unit, next to the unit.
+    fn env_path(&self) -> Result<PathBuf> {
+        Ok(self.path()?.with_extension("env"))
+    }
+
+    /// Write the definition for running spirit with `args` and start it.
+    ///
+    /// The service runs from the current directory, so a local spirit.toml
+    /// here applies, and is given `govee_key` in its environment. Whatever
+    /// file ends up holding the key is only readable by the current user.
+    pub fn install(&self, args: &[String], govee_key: &str) -> Result<()> {
+        let exe = std::env::current_exe().context("Could not find the spirit executable")?;
+        let cwd = std::env::current_dir()?;
+        let path = self.path()?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        if cfg!(target_os = "macos") {
+            write_private(&path, &self.plist(&exe, args, &cwd, govee_key))?;
+        } else {
+            let env_path = self.env_path()?;
+            write_private(
+                &env_path,
+                &format!(
+                    "GOVEE_KEY=\"{}\"\n",
+                    govee_key.replace('\\', "\\\\").replace('"', "\\\"")
+                ),
+            )?;
+            write_private(&path, &self.unit(&exe, args, &cwd, &env_path))?;
+        }
+
+        if cfg!(target_os = "macos") {
+            run("launchctl", &["load", "-w", &path.to_string_lossy()])
+        } else {
+            run("systemctl", &["--user", "daemon-reload"])?;
+            run(
+                "systemctl",
+                &[
+                    "--user",
+                    "enable",
+                    "--now",
+                    &format!("{}.service", self.label),
+                ],
+            )
+        }
+    }
+
+    pub fn uninstall(&self) -> Result<()> {
+        let path = self.path()?;
+
+        if !path.exists() {
+            bail!("{} is not installed", self.label);
+        }
+
+        if cfg!(target_os = "macos") {
+            run("launchctl", &["unload", "-w", &path.to_string_lossy()])?;
+        } else {
+            run(
+                "systemctl",
+                &[
+                    "--user",
+                    "disable",
+                    "--now",
+                    &format!("{}.service", self.label),
+                ],
+            )?;
+        }
+
+        fs::remove_file(&path).with_context(|| format!("Could not remove {}", path.display()))?;
+
+        if cfg!(target_os = "linux") {
+            let env_path = self.env_path()?;
+            if env_path.exists() {
+                fs::remove_file(&env_path)
+                    .with_context(|| format!("Could not remove {}", env_path.display()))?;
+            }
+
+            run("systemctl", &["--user", "daemon-reload"])?;
+        }
+
+        Ok(())
+    }
+
+    pub fn status(&self) -> Result<()> {
+        let path = self.path()?;
+
+        if !path.exists() {
+            println!("{} is not installed", self.label);
+            return Ok(());
+        }
+
+        println!("{} is installed at {}", self.label, path.display());
+
+        // both exit non-zero for stopped services, which is still a status
+        if cfg!(target_os = "macos") {
+            let _ = Command::new("launchctl")
+                .args(["list", &format!("com.{}", self.label)])
+                .status();
+        } else {
+            let _ = Command::new("systemctl")
+                .args([
+                    "--user",
+                    "status",
+                    "--no-pager",
+                    &format!("{}.service", self.label),
+                ])
+                .status();
+        }
+
+        Ok(())
+    }
+
+    fn unit(&self, exe: &Path, args: &[String], cwd: &Path, env_path: &Path) -> String {
+        let mut command = vec![quote_systemd(&exe.to_string_lossy())];
+        command.extend(args.iter().map(|a| quote_systemd(a)));
+
+        format!(
+            "[Unit]\n\
+             Description=spirit {args}\n\
+             After=network-online.target\n\
+             \n\
+             [Service]\n\
+             ExecStart={command}\n\
+             WorkingDirectory={cwd}\n\
+             EnvironmentFile={env}\n\
+             Restart=on-failure\n\
+             RestartSec=10\n\
+             \n\
+             [Install]\n\
+             WantedBy=default.target\n",
+            args = escape_systemd(&args.join(" ")),
+            command = command.join(" "),
+            cwd = escape_systemd(&cwd.to_string_lossy()),
+            env = escape_systemd(&env_path.to_string_lossy()),
+        )
+    }
+
+    fn plist(&self, exe: &Path, args: &[String], cwd: &Path, govee_key: &str) -> String {
+        let arguments: String = std::iter::once(exe.to_string_lossy().into_owned())
+            .chain(args.iter().cloned())
+            .map(|a| format!("        <string>{}</string>\n", escape_xml(&a)))
+            .collect();
+
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>com.{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+{arguments}    </array>
+    <key>WorkingDirectory</key>
+    <string>{cwd}</string>
+    <key>EnvironmentVariables</key>
+    <dict>
+        <key>GOVEE_KEY</key>
+        <string>{key}</string>
+    </dict>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <true/>
+</dict>
+</plist>
+"#,
+            label = self.label,
+            arguments = arguments,
+            cwd = escape_xml(&cwd.to_string_lossy()),
+            key = escape_xml(govee_key),
+        )
+    }
+}
+
+fn run(program: &str, args: &[&str]) -> Result<()> {
+    let status = Command::new(program)
+        .args(args)
+        .status()
+        .with_context(|| format!("Could not run {}", program))?;
+
+    if !status.success() {
+        bail!("{} {} exited with {}", program, args.join(" "), status);
+    }
+
+    Ok(())
+}
+
+/// Write `contents` to `path`, readable and writable only by the owner.
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        options.mode(0o600);
+
+        // the mode only applies to new files, so tighten one left by an
+        // earlier install too
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+                .with_context(|| format!("Could not restrict {}", path.display()))?;
+        }
+    }
+
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .with_context(|| format!("Could not write {}", path.display()))
+}
+
+/// Make `raw` safe to use as a unit file value: systemd expands `%`
+/// specifiers everywhere, and a newline would start a new setting.
+fn escape_systemd(raw: &str) -> String {
+    raw.replace('%', "%%").replace(['\n', '\r'], " ")
+}
+
+/// Quote `raw` as a single word of a unit's command line or environment.
+fn quote_systemd(raw: &str) -> String {
+    format!(
+        "\"{}\"",
+        escape_systemd(raw)
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('$', "$$")
+    )
+}
+
+fn escape_xml(raw: &str) -> String {
+    raw.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
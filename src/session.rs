@@ -0,0 +1,225 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// A selection, profile and quiet mode scoped to the shell that started it,
+/// used by every spirit command run from that shell until the session ends.
+///
+/// The shell is identified by spirit's parent process, so sessions don't
+/// leak into other terminals or panes. Its start time is kept too, so a
+/// later shell that happens to get the same pid doesn't inherit the session.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Session {
+    #[serde(default)]
+    pub all: bool,
+    #[serde(default)]
+    pub devices: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub quiet: bool,
+    /// When the shell started, as reported by the platform.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell_started: Option<String>,
+}
+
+impl Session {
+    /// The directory session files live in, `spirit` in the runtime (or
+    /// temp) directory so they don't outlive a reboot.
+    fn dir() -> PathBuf {
+        dirs::runtime_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("spirit")
+    }
+
+    /// The session file for the calling shell, `session-<pid>.toml` in the
+    /// session directory.
+    #[cfg(unix)]
+    pub fn path() -> Result<PathBuf> {
+        Ok(Self::path_for(std::os::unix::process::parent_id()))
+    }
+
+    #[cfg(not(unix))]
+    pub fn path() -> Result<PathBuf> {
+        anyhow::bail!("Sessions are only supported on unix")
+    }
+
+    fn path_for(pid: u32) -> PathBuf {
+        Self::dir().join(format!("session-{}.toml", pid))
+    }
+
+    /// When the calling shell started.
+    #[cfg(unix)]
+    pub fn shell_started() -> Option<String> {
+        started(std::os::unix::process::parent_id())
+    }
+
+    #[cfg(not(unix))]
+    pub fn shell_started() -> Option<String> {
+        None
+    }
+
+    /// The calling shell's session, if it has one.
+    ///
+    /// A session left behind by an earlier shell with the same pid is
+    /// removed instead.
+    pub fn load() -> Result<Option<Self>> {
+        if cfg!(not(unix)) {
+            return Ok(None);
+        }
+
+        let path = Self::path()?;
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let raw = fs::read_to_string(&path)
+            .with_context(|| format!("Could not read session file {}", path.display()))?;
+
+        let session: Self = toml::from_str(&raw)
+            .with_context(|| format!("Could not parse session file {}", path.display()))?;
+
+        if session.shell_started != Self::shell_started() {
+            Self::end()?;
+            return Ok(None);
+        }
+
+        Ok(Some(session))
+    }
+
+    /// Save the session for the calling shell, clearing out any left behind
+    /// by shells that have since exited.
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        Self::remove_stale();
+
+        fs::write(&path, toml::to_string_pretty(self)?)
+            .with_context(|| format!("Could not write session file {}", path.display()))
+    }
+
+    /// Remove the calling shell's session, returning whether there was one.
+    pub fn end() -> Result<bool> {
+        let path = Self::path()?;
+
+        if !path.exists() {
+            return Ok(false);
+        }
+
+        fs::remove_file(&path)
+            .with_context(|| format!("Could not remove session file {}", path.display()))?;
+
+        Ok(true)
+    }
+
+    /// Best effort removal of session files whose shell is gone, since
+    /// shells exit without running `spirit session end`.
+    #[cfg(unix)]
+    fn remove_stale() {
+        let entries = match fs::read_dir(Self::dir()) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let pid = name
+                .to_str()
+                .and_then(|n| n.strip_prefix("session-"))
+                .and_then(|n| n.strip_suffix(".toml"))
+                .and_then(|pid| pid.parse::<u32>().ok());
+
+            let pid = match pid {
+                Some(pid) => pid,
+                None => continue,
+            };
+
+            let session: Option<Self> = fs::read_to_string(entry.path())
+                .ok()
+                .and_then(|raw| toml::from_str(&raw).ok());
+
+            let alive = match (started(pid), session) {
+                (Some(started), Some(session)) => session.shell_started == Some(started),
+                _ => false,
+            };
+
+            if !alive {
+                let _ = fs::remove_file(Self::path_for(pid));
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn remove_stale() {}
+
+    /// Whether the session picks devices, rather than only a profile or
+    /// quiet mode.
+    pub fn selects(&self) -> bool {
+        self.all || !self.devices.is_empty() || !self.groups.is_empty()
+    }
+
+    pub fn describe(&self) -> String {
+        let mut out = if self.all {
+            "all devices".to_string()
+        } else if self.selects() {
+            let mut parts = Vec::new();
+            if !self.devices.is_empty() {
+                parts.push(format!("devices {}", self.devices.join(", ")));
+            }
+            if !self.groups.is_empty() {
+                parts.push(format!("groups {}", self.groups.join(", ")));
+            }
+            parts.join(" and ")
+        } else {
+            "the usual devices".to_string()
+        };
+
+        if let Some(ref profile) = self.profile {
+            out.push_str(&format!(" with profile {}", profile));
+        }
+        if self.quiet {
+            out.push_str(", quietly");
+        }
+
+        out
+    }
+}
+
+/// When the process `pid` started, or `None` if there's no such process.
+///
+/// The value is only compared for equality, so it's whatever the platform
+/// reports: clock ticks since boot from `/proc` on Linux, `ps` elsewhere.
+#[cfg(unix)]
+fn started(pid: u32) -> Option<String> {
+    if let Ok(stat) = fs::read_to_string(format!("/proc/{}/stat", pid)) {
+        // the command name can contain spaces and parens, so count from the
+        // end of it; the start time is the 22nd field
+        return stat
+            .rsplit(')')
+            .next()?
+            .split_whitespace()
+            .nth(19)
+            .map(String::from);
+    }
+
+    let out = std::process::Command::new("ps")
+        .args(["-o", "lstart=", "-p", &pid.to_string()])
+        .output()
+        .ok()?;
+    let started = String::from_utf8_lossy(&out.stdout).trim().to_string();
+
+    if out.status.success() && !started.is_empty() {
+        Some(started)
+    } else {
+        None
+    }
+}
@@ -1,56 +1,240 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use std::collections::HashMap;
 use std::ffi::OsStr;
-use std::path::Path;
+use std::fs;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 
 use govee_rs::models::Color;
-use serde::Deserialize;
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::notify::Event;
 
 fn default_success() -> String {
     "#00ff00".into()
 }
 
 fn default_fail() -> String {
-    "#00ff00".into()
+    "#ff0000".into()
+}
+
+/// How named lists (devices, checks, buttons, palettes, signals and
+/// notifiers) combine when more than one config file defines them.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ListMerge {
+    /// The nearest file's list replaces the others entirely.
+    #[default]
+    Replace,
+    /// Lists from every file are concatenated.
+    Append,
+    /// Entries are matched by name, with nearer files overriding farther
+    /// ones and new names added.
+    ByName,
+}
+
+/// What attention effects like flashes and blinking patterns do while the
+/// desktop's do-not-disturb or focus mode is on.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum DndMode {
+    /// Play them as usual.
+    #[default]
+    Ignore,
+    /// Play them once, without repeats.
+    Downgrade,
+    /// Don't play them.
+    Suppress,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct Settings {
+    #[serde(default)]
+    pub list_merge: ListMerge,
     pub default: Option<String>,
     pub devices: Option<Vec<DeviceSetting>>,
+    /// The color `spirit check` shows on success when neither the command
+    /// line nor the check sets one. Green unless configured.
     #[serde(default = "default_success")]
     pub success: String,
+    /// The color `spirit check` shows on failure when neither the command
+    /// line nor the check sets one. Red unless configured.
     #[serde(default = "default_fail")]
     pub fail: String,
+    #[cfg(feature = "hotkeys")]
+    pub hotkeys: Option<Vec<HotkeySetting>>,
+    pub buttons: Option<Vec<ButtonSetting>>,
+    pub concurrency: Option<ConcurrencySetting>,
+    pub serve: Option<ServeSetting>,
+    #[serde(default)]
+    pub dnd: DndMode,
+    pub flux: Option<Vec<FluxPoint>>,
+    pub keylight: Option<KeylightSetting>,
+    pub palettes: Option<Vec<PaletteSetting>>,
+    pub checks: Option<Vec<CheckSetting>>,
+    pub signals: Option<Vec<SignalSetting>>,
+    pub notifiers: Option<Vec<NotifierSetting>>,
+    pub safety: Option<SafetySetting>,
+    pub auto_off: Option<Vec<AutoOffSetting>>,
+    /// Color corrections by device model, e.g. `[corrections.H6159]`.
+    pub corrections: Option<HashMap<String, CorrectionSetting>>,
+    /// Watts drawn at full brightness by device model, for `spirit report
+    /// energy`, e.g. `[wattage]` then `H6159 = 12`.
+    pub wattage: Option<HashMap<String, f64>>,
+    /// Named sets of overrides picked with `--profile`, e.g.
+    /// `[profiles.presenting]` then `dnd = "suppress"`.
+    pub profiles: Option<HashMap<String, ProfileSetting>>,
 }
 
 impl Settings {
-    pub fn new() -> Result<Option<Self>> {
+    /// Load and merge the config files.
+    ///
+    /// With no config files at all this is the built-in defaults, so
+    /// commands given explicit devices work without any setup.
+    pub fn new() -> Result<Self> {
+        let paths = Self::config_paths();
+
         let mut settings = config::Config::new();
-        let mut loaded = false;
+
+        for path in paths.iter() {
+            if let Some(path) = path.to_str() {
+                settings.merge(config::File::with_name(path))?;
+            } else {
+                bail!("Could not make config file path");
+            }
+        }
+
+        let mut settings: Self = settings.try_into()?;
+
+        if settings.list_merge != ListMerge::Replace {
+            settings.merge_lists(&paths)?;
+        }
+
+        Ok(settings)
+    }
+
+    /// Apply the overrides from the profile called `name`.
+    pub fn use_profile(&mut self, name: &str) -> Result<()> {
+        let profile = match self.profiles.as_ref().and_then(|p| p.get(name)) {
+            Some(profile) => profile.clone(),
+            None => bail!("No profile named '{}' in config", name),
+        };
+
+        if profile.default.is_some() {
+            self.default = profile.default;
+        }
+        if let Some(success) = profile.success {
+            self.success = success;
+        }
+        if let Some(fail) = profile.fail {
+            self.fail = fail;
+        }
+        if let Some(dnd) = profile.dnd {
+            self.dnd = dnd;
+        }
+
+        Ok(())
+    }
+
+    /// Re-read the named lists from each config layer and combine them
+    /// according to `list_merge`, instead of letting the nearest win.
+    fn merge_lists(&mut self, paths: &[PathBuf]) -> Result<()> {
+        let mut layers = Vec::with_capacity(paths.len());
+        for path in paths {
+            let raw = fs::read_to_string(path)
+                .with_context(|| format!("Could not read {}", path.display()))?;
+            let layer: toml::Value = toml::from_str(&raw)
+                .with_context(|| format!("Could not parse {}", path.display()))?;
+            layers.push(layer);
+        }
+
+        let strategy = self.list_merge;
+
+        self.devices = merge_list(&layers, "devices", strategy, |d: &DeviceSetting| &d.name)?;
+        self.checks = merge_list(&layers, "checks", strategy, |c: &CheckSetting| &c.name)?;
+        self.buttons = merge_list(&layers, "buttons", strategy, |b: &ButtonSetting| &b.name)?;
+        self.palettes = merge_list(&layers, "palettes", strategy, |p: &PaletteSetting| &p.name)?;
+        self.signals = merge_list(&layers, "signals", strategy, |s: &SignalSetting| &s.name)?;
+        self.notifiers = merge_list(&layers, "notifiers", strategy, |n: &NotifierSetting| {
+            &n.name
+        })?;
+
+        Ok(())
+    }
+
+    /// The config files that exist, in the order they are merged.
+    ///
+    /// The global spirit.toml in the home directory comes first, followed by
+    /// any spirit.toml files between the root of the enclosing git repository
+    /// and the current directory, so that nearer files override farther ones.
+    pub fn config_paths() -> Vec<PathBuf> {
+        let mut paths = Vec::new();
 
         if let Some(home) = dirs::home_dir() {
             let global_config = home.join(Path::new(OsStr::new("spirit.toml")));
             if global_config.exists() {
-                if let Some(path) = global_config.to_str() {
-                    settings.merge(config::File::with_name(path))?;
-                    loaded = true;
-                } else {
-                    bail!("Could not make global config file path");
-                }
+                paths.push(global_config);
             }
         }
 
-        if Path::new(OsStr::new("spirit.toml")).exists() {
-            settings.merge(config::File::with_name("spirit"))?;
-            loaded = true;
+        for local_config in Self::local_paths().into_iter().rev() {
+            if !paths.contains(&local_config) {
+                paths.push(local_config);
+            }
         }
 
-        if loaded {
-            Ok(Some(settings.try_into()?))
-        } else {
-            Ok(None)
+        paths
+    }
+
+    /// The config file commands that edit config should write to.
+    ///
+    /// This is the nearest local spirit.toml if there is one, otherwise the
+    /// global one in the home directory.
+    pub fn writable_path() -> Result<PathBuf> {
+        if let Some(local) = Self::local_paths().into_iter().next() {
+            return Ok(local);
+        }
+
+        match dirs::home_dir() {
+            Some(home) => Ok(home.join(Path::new(OsStr::new("spirit.toml")))),
+            None => bail!("Could not determine home directory for config file"),
+        }
+    }
+
+    /// The local spirit.toml files that exist, nearest first.
+    ///
+    /// Directories are searched upward from the current one, stopping at the
+    /// first one containing `.git`. Outside a git repository only the current
+    /// directory is searched.
+    fn local_paths() -> Vec<PathBuf> {
+        let cwd = match std::env::current_dir() {
+            Ok(cwd) => cwd,
+            Err(_) => {
+                let local = Path::new(OsStr::new("spirit.toml"));
+                return if local.exists() {
+                    vec![local.to_path_buf()]
+                } else {
+                    Vec::new()
+                };
+            }
+        };
+
+        let in_repo = cwd.ancestors().any(|dir| dir.join(".git").exists());
+
+        let mut paths = Vec::new();
+
+        for dir in cwd.ancestors() {
+            let config = dir.join("spirit.toml");
+            if config.exists() {
+                paths.push(config);
+            }
+
+            if !in_repo || dir.join(".git").exists() {
+                break;
+            }
         }
+
+        paths
     }
 
     pub fn device_settings(&self) -> DeviceSettingMap {
@@ -65,12 +249,333 @@ impl Settings {
     }
 }
 
+fn merge_list<T, F>(
+    layers: &[toml::Value],
+    key: &str,
+    strategy: ListMerge,
+    name: F,
+) -> Result<Option<Vec<T>>>
+where
+    T: DeserializeOwned,
+    F: Fn(&T) -> &String,
+{
+    let mut merged: Option<Vec<T>> = None;
+
+    for layer in layers {
+        let entries: Vec<T> = match layer.get(key) {
+            Some(value) => value
+                .clone()
+                .try_into()
+                .with_context(|| format!("Invalid '{}' list in config", key))?,
+            None => continue,
+        };
+
+        let list = merged.get_or_insert_with(Vec::new);
+
+        for entry in entries {
+            let existing = match strategy {
+                ListMerge::ByName => list.iter().position(|e| name(e) == name(&entry)),
+                _ => None,
+            };
+
+            match existing {
+                Some(i) => list[i] = entry,
+                None => list.push(entry),
+            }
+        }
+    }
+
+    Ok(merged)
+}
+
+/// How commands reach a device.
+///
+/// `auto` uses the LAN api when the device has an `ip` and the cloud api
+/// otherwise. With an `ip`, only state reads fall back to the cloud when the
+/// device doesn't answer on the LAN; changes are sent over the LAN regardless.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    Lan,
+    Cloud,
+    #[default]
+    Auto,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct DeviceSetting {
     pub name: String,
+    /// The govee device id, used to notice when a device has been renamed.
+    pub id: Option<String>,
     pub color: Option<String>,
     pub success: Option<String>,
     pub fail: Option<String>,
+    pub transport: Option<Transport>,
+    pub ip: Option<IpAddr>,
+    /// Free-form human context, shown by list and info.
+    pub notes: Option<String>,
+    pub location: Option<String>,
+    /// A friendlier name that --device also accepts.
+    pub alias: Option<String>,
+    pub groups: Option<Vec<String>>,
+    /// Shell commands `spirit watch` runs when it sees the device change
+    /// power state.
+    pub on_power_on: Option<String>,
+    pub on_power_off: Option<String>,
+    /// Replaces any correction for the device's model.
+    pub correction: Option<CorrectionSetting>,
+    /// Limits every brightness spirit sets on the device, in percent.
+    pub min_brightness: Option<u8>,
+    pub max_brightness: Option<u8>,
+}
+
+impl DeviceSetting {
+    pub fn in_any_group(&self, groups: &[String]) -> bool {
+        self.groups
+            .as_ref()
+            .map(|own| own.iter().any(|g| groups.contains(g)))
+            .unwrap_or(false)
+    }
+
+    /// The range brightness is clamped to, if either limit is set.
+    pub fn brightness_range(&self) -> Result<Option<(u8, u8)>> {
+        if self.min_brightness.is_none() && self.max_brightness.is_none() {
+            return Ok(None);
+        }
+
+        let min = self.min_brightness.unwrap_or(0);
+        let max = self.max_brightness.unwrap_or(100);
+
+        if max > 100 {
+            bail!("max_brightness {} is above 100", max);
+        }
+        if min > max {
+            bail!("min_brightness {} is above max_brightness {}", min, max);
+        }
+
+        Ok(Some((min, max)))
+    }
+
+    /// The location and notes as a single line, if either is set.
+    pub fn describe(&self) -> Option<String> {
+        match (self.location.as_deref(), self.notes.as_deref()) {
+            (Some(location), Some(notes)) => Some(format!("{} - {}", location, notes)),
+            (Some(only), None) | (None, Some(only)) => Some(only.to_string()),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Color correction for devices that render some hues poorly.
+///
+/// `lut` maps exact colors to what should be sent instead, e.g.
+/// `lut = { "#ffa500" = "#ff7000" }`. Other colors are scaled so `white` is
+/// sent for white, then raised to `gamma`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CorrectionSetting {
+    pub gamma: Option<f64>,
+    pub white: Option<String>,
+    pub lut: Option<HashMap<String, String>>,
+}
+
+/// A config-defined action, shared by hotkeys and http buttons.
+///
+/// `action` is one of `toggle`, `on`, `off`, `color` (using `color`),
+/// `brightness` (using `brightness`) or `scene` (using `scene`).
+#[derive(Debug, Deserialize, Clone)]
+pub struct ActionSetting {
+    pub action: String,
+    pub color: Option<String>,
+    pub brightness: Option<u8>,
+    pub scene: Option<String>,
+}
+
+/// A global shortcut binding for `spirit hotkeys`.
+#[cfg(feature = "hotkeys")]
+#[derive(Debug, Deserialize, Clone)]
+pub struct HotkeySetting {
+    pub keys: String,
+    #[serde(flatten)]
+    pub action: ActionSetting,
+}
+
+/// A named button for `spirit serve`, triggered by `GET /press/<name>`.
+///
+/// If `devices` is set the action only applies to those of the selected
+/// devices.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ButtonSetting {
+    pub name: String,
+    #[serde(flatten)]
+    pub action: ActionSetting,
+    pub devices: Option<Vec<String>>,
+}
+
+/// Caps on how many device operations run at once.
+///
+/// `cloud` and `lan` limit devices by transport (`auto` devices with an ip
+/// count as lan), and `groups` limits the members of each named group, e.g.
+/// `[concurrency.groups]` then `office = 1`. A device waits for a slot under
+/// every limit that applies to it. Anything unset is unlimited.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ConcurrencySetting {
+    pub cloud: Option<usize>,
+    pub lan: Option<usize>,
+    pub groups: Option<HashMap<String, usize>>,
+}
+
+/// What a `spirit serve` token is allowed to do.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Only read device state.
+    Read,
+    /// Read device state and press buttons.
+    Control,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TokenSetting {
+    pub token: String,
+    pub scope: Scope,
+}
+
+/// Settings for `spirit serve`, whose access rules `spirit stream` follows
+/// too.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ServeSetting {
+    /// If set, every request must present one of these tokens.
+    pub tokens: Option<Vec<TokenSetting>>,
+    /// If set, only clients with these addresses may connect. Entries are
+    /// plain addresses or CIDR ranges.
+    pub allowed_ips: Option<Vec<String>>,
+}
+
+/// A point on the `spirit flux` color temperature curve.
+#[derive(Debug, Deserialize, Clone)]
+pub struct FluxPoint {
+    /// The time of day, as HH:MM.
+    pub time: String,
+    pub kelvin: u32,
+}
+
+fn default_keylight_kelvin() -> u32 {
+    5000
+}
+
+fn default_keylight_brightness() -> u8 {
+    80
+}
+
+/// The preset for `spirit keylight`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct KeylightSetting {
+    /// The device to use. Defaults to the selected devices.
+    pub device: Option<String>,
+    #[serde(default = "default_keylight_kelvin")]
+    pub kelvin: u32,
+    #[serde(default = "default_keylight_brightness")]
+    pub brightness: u8,
+}
+
+/// A shared check definition, run with `spirit check --name <name>`.
+///
+/// `devices` and `groups` narrow the selected devices, and the colors
+/// apply unless overridden on the command line.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CheckSetting {
+    pub name: String,
+    /// Run with `sh -c`.
+    pub command: String,
+    pub devices: Option<Vec<String>>,
+    pub groups: Option<Vec<String>>,
+    pub success: Option<String>,
+    pub fail: Option<String>,
+}
+
+/// A named list of colors, used by `spirit ambient-drift`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct PaletteSetting {
+    pub name: String,
+    pub colors: Vec<String>,
+}
+
+/// A named blink pattern for `spirit signal --name <name>`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SignalSetting {
+    pub name: String,
+    pub pattern: String,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifierKind {
+    Desktop,
+    Webhook,
+    Email,
+    Command,
+}
+
+impl std::fmt::Display for NotifierKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Desktop => "desktop",
+            Self::Webhook => "webhook",
+            Self::Email => "email",
+            Self::Command => "command",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// Where to send notifications, and which ones.
+///
+/// `url` is used by webhooks, `to` by email (sent with the local sendmail)
+/// and `command` by command notifiers. Without `events` every event is
+/// sent.
+#[derive(Debug, Deserialize, Clone)]
+pub struct NotifierSetting {
+    pub name: String,
+    pub kind: NotifierKind,
+    pub events: Option<Vec<Event>>,
+    pub url: Option<String>,
+    pub to: Option<String>,
+    pub command: Option<String>,
+}
+
+/// Overrides for the top-level settings of the same names, used while a
+/// profile is picked with `--profile` or by a session.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ProfileSetting {
+    pub default: Option<String>,
+    pub success: Option<String>,
+    pub fail: Option<String>,
+    pub dnd: Option<DndMode>,
+}
+
+/// When commands that change devices should ask first.
+///
+/// Either condition triggers a confirmation prompt, which `--yes` skips.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct SafetySetting {
+    /// Confirm when more than this many devices are selected.
+    pub max_devices: Option<usize>,
+    /// Confirm whenever --all is used.
+    #[serde(default)]
+    pub confirm_all: bool,
+}
+
+/// Turn off (or dim to `dim` percent) devices `spirit watch` sees on for
+/// longer than `after` (e.g. "3h"), optionally only `between` certain
+/// hours (e.g. "23:00-06:00").
+#[derive(Debug, Deserialize, Clone)]
+pub struct AutoOffSetting {
+    pub devices: Option<Vec<String>>,
+    pub groups: Option<Vec<String>>,
+    pub after: String,
+    pub between: Option<String>,
+    pub dim: Option<u8>,
 }
 
 #[derive(Debug, Default)]
@@ -81,6 +586,10 @@ impl DeviceSettingMap {
         self.0.get(name)
     }
 
+    pub fn find_by_id(&self, id: &str) -> Option<&DeviceSetting> {
+        self.0.values().find(|s| s.id.as_deref() == Some(id))
+    }
+
     pub fn default_color(
         &self,
         name: &str,
@@ -123,3 +632,117 @@ impl DeviceSettingMap {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Entry {
+        name: String,
+        value: i64,
+    }
+
+    fn layers(raw: &[&str]) -> Vec<toml::Value> {
+        raw.iter().map(|r| toml::from_str(r).unwrap()).collect()
+    }
+
+    fn merged(layers: &[toml::Value], strategy: ListMerge) -> Vec<(String, i64)> {
+        merge_list(layers, "entries", strategy, |e: &Entry| &e.name)
+            .unwrap()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|e| (e.name, e.value))
+            .collect()
+    }
+
+    fn entry(name: &str, value: i64) -> (String, i64) {
+        (name.to_string(), value)
+    }
+
+    const GLOBAL: &str = r#"
+        [[entries]]
+        name = "a"
+        value = 1
+
+        [[entries]]
+        name = "b"
+        value = 2
+    "#;
+
+    const LOCAL: &str = r#"
+        [[entries]]
+        name = "b"
+        value = 20
+
+        [[entries]]
+        name = "c"
+        value = 30
+    "#;
+
+    #[test]
+    fn by_name_lets_nearer_files_win() {
+        let layers = layers(&[GLOBAL, LOCAL]);
+
+        assert_eq!(
+            merged(&layers, ListMerge::ByName),
+            vec![entry("a", 1), entry("b", 20), entry("c", 30)]
+        );
+    }
+
+    #[test]
+    fn by_name_follows_file_order() {
+        let layers = layers(&[LOCAL, GLOBAL]);
+
+        assert_eq!(
+            merged(&layers, ListMerge::ByName),
+            vec![entry("b", 2), entry("c", 30), entry("a", 1)]
+        );
+    }
+
+    #[test]
+    fn append_keeps_every_entry() {
+        let layers = layers(&[GLOBAL, LOCAL]);
+
+        assert_eq!(
+            merged(&layers, ListMerge::Append),
+            vec![entry("a", 1), entry("b", 2), entry("b", 20), entry("c", 30)]
+        );
+    }
+
+    #[test]
+    fn layers_without_the_list_are_skipped() {
+        let with_gaps = layers(&["other = 1", GLOBAL, ""]);
+
+        assert_eq!(
+            merged(&with_gaps, ListMerge::ByName),
+            vec![entry("a", 1), entry("b", 2)]
+        );
+
+        let none = merge_list(
+            &layers(&["", "other = 1"]),
+            "entries",
+            ListMerge::ByName,
+            |e: &Entry| &e.name,
+        )
+        .unwrap();
+        assert!(none.is_none());
+    }
+
+    #[test]
+    fn rejects_malformed_lists() {
+        for raw in [
+            "entries = 3",
+            "entries = [{ name = \"a\" }]",
+            "entries = [{ name = \"a\", value = \"one\" }]",
+        ] {
+            let res = merge_list(
+                &layers(&[raw]),
+                "entries",
+                ListMerge::ByName,
+                |e: &Entry| &e.name,
+            );
+            assert!(res.is_err(), "{} should be rejected", raw);
+        }
+    }
+}
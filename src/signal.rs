@@ -0,0 +1,178 @@
+use anyhow::{bail, Result};
+use std::time::Duration;
+
+use govee_rs::models::{Color, Devices, PowerState};
+
+use crate::{capability, client::Client, state::DeviceSnapshot};
+
+/// One stretch of a blink pattern, in multiples of the unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Step {
+    on: bool,
+    units: u32,
+}
+
+/// A blink rhythm in morse-style notation.
+///
+/// `.` is on for one unit and `-` is on for three, with one unit off
+/// between them. A space is a three unit gap and `/` a seven unit gap.
+#[derive(Debug, Clone)]
+pub struct Pattern(Vec<Step>);
+
+impl Pattern {
+    pub fn parse(raw: &str) -> Result<Self> {
+        let mut steps: Vec<Step> = Vec::new();
+
+        for c in raw.chars() {
+            let step = match c {
+                '.' => Step { on: true, units: 1 },
+                '-' => Step { on: true, units: 3 },
+                ' ' => Step {
+                    on: false,
+                    units: 3,
+                },
+                '/' => Step {
+                    on: false,
+                    units: 7,
+                },
+                other => bail!("Invalid character '{}' in pattern '{}'", other, raw),
+            };
+
+            match steps.last().copied() {
+                Some(last) if last.on && step.on => {
+                    steps.push(Step {
+                        on: false,
+                        units: 1,
+                    });
+                    steps.push(step);
+                }
+                Some(last) if !last.on && !step.on => {
+                    // consecutive pauses merge into the longest
+                    steps.pop();
+                    steps.push(Step {
+                        on: false,
+                        units: last.units.max(step.units),
+                    });
+                }
+                _ => steps.push(step),
+            }
+        }
+
+        if !steps.iter().any(|s| s.on) {
+            bail!("Pattern '{}' never turns on", raw);
+        }
+
+        Ok(Self(steps))
+    }
+}
+
+/// Blink `devices` through `pattern` in `color` together, `repeat` times,
+/// then restore them.
+///
+/// Each change is a request per device, so units much shorter than the
+/// request latency will blur together; the LAN transport copes best.
+pub async fn play(
+    client: &Client,
+    devices: &Devices,
+    pattern: &Pattern,
+    color: &Color,
+    unit: Duration,
+    repeat: usize,
+) -> Result<()> {
+    let mut previous: Vec<DeviceSnapshot> = Vec::new();
+    for device in devices.iter() {
+        previous.push(client.snapshot(device).await?);
+    }
+
+    let res = async {
+        for round in 0..repeat {
+            if round > 0 {
+                tokio::time::sleep(unit * 7).await;
+            }
+
+            for step in pattern.0.iter() {
+                let on = step.on;
+                let results = client
+                    .each(devices.iter(), |client, device| {
+                        let color = color.clone();
+                        async move {
+                            if on && capability::has_color(&device) {
+                                client.color(&device, color).await
+                            } else if on {
+                                client.turn(&device, PowerState::On).await
+                            } else {
+                                client.turn(&device, PowerState::Off).await
+                            }
+                        }
+                    })
+                    .await;
+
+                for (_, res) in results {
+                    res?;
+                }
+
+                tokio::time::sleep(unit * step.units).await;
+            }
+        }
+
+        Ok::<(), anyhow::Error>(())
+    }
+    .await;
+
+    // restore even if signaling failed or was cancelled part way through
+    let restoring = client.restoring();
+    for (device, snapshot) in devices.iter().zip(previous.iter()) {
+        snapshot.apply(&restoring, device).await?;
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn on(units: u32) -> Step {
+        Step { on: true, units }
+    }
+
+    fn off(units: u32) -> Step {
+        Step { on: false, units }
+    }
+
+    #[test]
+    fn marks_are_separated_by_one_unit() {
+        let pattern = Pattern::parse("..-").unwrap();
+
+        assert_eq!(pattern.0, vec![on(1), off(1), on(1), off(1), on(3)]);
+    }
+
+    #[test]
+    fn gaps_replace_the_separator() {
+        let pattern = Pattern::parse(". -/.").unwrap();
+
+        assert_eq!(pattern.0, vec![on(1), off(3), on(3), off(7), on(1)]);
+    }
+
+    #[test]
+    fn consecutive_gaps_merge_into_the_longest() {
+        let pattern = Pattern::parse(".  / .").unwrap();
+
+        assert_eq!(pattern.0, vec![on(1), off(7), on(1)]);
+    }
+
+    #[test]
+    fn leading_and_trailing_gaps_are_kept() {
+        let pattern = Pattern::parse(" . ").unwrap();
+
+        assert_eq!(pattern.0, vec![off(3), on(1), off(3)]);
+    }
+
+    #[test]
+    fn rejects_malformed_patterns() {
+        assert!(Pattern::parse("").is_err());
+        assert!(Pattern::parse(" / ").is_err());
+        assert!(Pattern::parse("..x").is_err());
+        assert!(Pattern::parse("._.").is_err());
+    }
+}
@@ -0,0 +1,93 @@
+use anyhow::Result;
+use govee_rs::models::{Color, Device, DeviceState, PowerState, Property};
+use serde::{Deserialize, Serialize};
+
+use crate::{capability, client::Client, lan::LanStatus};
+
+/// A point-in-time view of the parts of a device's state spirit can restore.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceSnapshot {
+    /// Whether the device is reachable. Not worth restoring, so not saved.
+    #[serde(skip)]
+    pub online: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub power: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+}
+
+impl DeviceSnapshot {
+    /// Push this snapshot back onto `device`.
+    ///
+    /// A device that was off is simply turned off again, otherwise the
+    /// color and brightness are reapplied (which also powers it on). The
+    /// color is what the device reported, so it isn't corrected again.
+    pub async fn apply(&self, client: &Client, device: &Device) -> Result<()> {
+        if self.power == Some(false) {
+            client.turn(device, PowerState::Off).await?;
+            return Ok(());
+        }
+
+        if !capability::is_light(device) {
+            client.turn(device, PowerState::On).await?;
+            return Ok(());
+        }
+
+        match self.color {
+            Some(ref color) if capability::has_color(device) => {
+                client.color_raw(device, Color::parse(color)?).await?;
+            }
+            _ => client.turn(device, PowerState::On).await?,
+        }
+
+        if let Some(brightness) = self
+            .brightness
+            .filter(|_| capability::supports(device, "brightness"))
+        {
+            client.brightness(device, brightness).await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<&DeviceState> for DeviceSnapshot {
+    fn from(state: &DeviceState) -> Self {
+        let mut snapshot = Self::default();
+
+        for property in state.properties.iter() {
+            match property {
+                Property::Online(online) => {
+                    snapshot.online = Some(*online);
+                }
+                Property::PowerState(power) => {
+                    snapshot.power = Some(*power == PowerState::On);
+                }
+                Property::Brightness(brightness) => {
+                    snapshot.brightness = Some(*brightness);
+                }
+                Property::Color(color) => {
+                    snapshot.color =
+                        Some(format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b));
+                }
+                _ => {}
+            }
+        }
+
+        snapshot
+    }
+}
+
+impl From<&LanStatus> for DeviceSnapshot {
+    fn from(status: &LanStatus) -> Self {
+        Self {
+            // it answered, so it's online
+            online: Some(true),
+            power: Some(status.on_off == 1),
+            brightness: Some(status.brightness),
+            color: Some(status.color.to_string()),
+        }
+    }
+}
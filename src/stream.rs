@@ -0,0 +1,157 @@
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use govee_rs::models::Devices;
+use tokio::{
+    net::UdpSocket,
+    time::{Instant, MissedTickBehavior},
+};
+
+use crate::{
+    client::Client,
+    color::Rgb,
+    events,
+    serve::Access,
+    settings::{Scope, Settings},
+};
+
+/// Where a device is heading and where it started from.
+struct Fade {
+    from: Rgb,
+    to: Rgb,
+    started: Instant,
+    sent: Option<Rgb>,
+}
+
+impl Fade {
+    fn at(&self, now: Instant, smoothing: Duration) -> Rgb {
+        if smoothing.is_zero() {
+            return self.to;
+        }
+
+        let t = now.duration_since(self.started).as_secs_f64() / smoothing.as_secs_f64();
+        self.from.lerp(self.to, t.min(1.0))
+    }
+}
+
+/// Accept color frames over udp on `addr` and play them on `devices` until
+/// interrupted.
+///
+/// Each datagram is a json object mapping device names (or `*` for every
+/// device) to #rrggbb colors. Devices are updated at most `fps` times a
+/// second with only the latest color, and each new color is faded to over
+/// `smoothing`, so senders can push frames at whatever rate suits them.
+///
+/// Frames are held to the same `[serve]` access rules as `spirit serve`:
+/// datagrams from addresses outside `allowed_ips` are dropped, and if tokens
+/// are configured each frame must carry one with the control scope under a
+/// `token` key.
+pub async fn stream(
+    client: &Client,
+    settings: &Settings,
+    devices: &Devices,
+    addr: SocketAddr,
+    fps: u32,
+    smoothing: Duration,
+) -> Result<()> {
+    let access = Access::from_settings(settings)?;
+
+    let socket = UdpSocket::bind(addr)
+        .await
+        .with_context(|| format!("Could not listen on {}", addr))?;
+
+    events::record("stream", format!("listening on {}", addr));
+    println!("Listening for frames on udp://{}", socket.local_addr()?);
+
+    let mut fades: HashMap<String, Fade> = HashMap::new();
+    let mut tick = tokio::time::interval(Duration::from_secs_f64(1.0 / f64::from(fps.max(1))));
+    // slow sends should drop frames rather than queue up a burst
+    tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut buf = vec![0; 65536];
+
+    loop {
+        tokio::select! {
+            received = socket.recv_from(&mut buf) => {
+                let (len, peer) = received?;
+
+                if !access.allows(peer.ip()) {
+                    events::record("refused", format!("frame from {}", peer));
+                    continue;
+                }
+
+                let mut frame: BTreeMap<String, String> = match serde_json::from_slice(&buf[..len]) {
+                    Ok(frame) => frame,
+                    Err(e) => {
+                        eprintln!("Ignoring bad frame from {}: {}", peer, e);
+                        continue;
+                    }
+                };
+
+                let token = frame.remove("token");
+                if access.scope(token.as_deref()) != Some(Scope::Control) {
+                    events::record("refused", format!("frame from {} without a control token", peer));
+                    continue;
+                }
+
+                let now = Instant::now();
+
+                for (name, raw) in frame.iter() {
+                    let color = match Rgb::parse(raw) {
+                        Ok(color) => color,
+                        Err(e) => {
+                            eprintln!("Ignoring bad color from {}: {:#}", peer, e);
+                            continue;
+                        }
+                    };
+
+                    for device in devices.iter().filter(|d| name == "*" || d.name == *name) {
+                        let fade = fades.entry(device.name.clone()).or_insert(Fade {
+                            from: color,
+                            to: color,
+                            started: now,
+                            sent: None,
+                        });
+
+                        fade.from = fade.at(now, smoothing);
+                        fade.to = color;
+                        fade.started = now;
+                    }
+                }
+            }
+            _ = tick.tick() => {
+                let now = Instant::now();
+
+                let mut pending = Vec::new();
+                for device in devices.iter() {
+                    if let Some(fade) = fades.get_mut(&device.name) {
+                        let color = fade.at(now, smoothing);
+                        if fade.sent != Some(color) {
+                            fade.sent = Some(color);
+                            pending.push((device, color));
+                        }
+                    }
+                }
+
+                let colors: HashMap<&str, Rgb> = pending
+                    .iter()
+                    .map(|(device, color)| (device.name.as_str(), *color))
+                    .collect();
+
+                let results = client
+                    .each(pending.iter().map(|(device, _)| *device), |client, device| {
+                        let color = colors[device.name.as_str()];
+                        async move { client.color(&device, color.to_color()?).await }
+                    })
+                    .await;
+
+                for (device, res) in results {
+                    if let Err(e) = res {
+                        eprintln!("Could not set {}: {:#}", device.name, e);
+                    }
+                }
+            }
+        }
+    }
+}
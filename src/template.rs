@@ -0,0 +1,59 @@
+use anyhow::{Context, Result};
+
+use govee_rs::models::Device;
+use minijinja::Environment;
+use serde::Serialize;
+
+use crate::{capability::Kind, settings::DeviceSetting, state::DeviceSnapshot};
+
+/// The variables available to `--template`.
+#[derive(Debug, Serialize)]
+pub struct DeviceContext<'a> {
+    pub name: &'a str,
+    pub id: &'a str,
+    pub model: &'a str,
+    /// "light" or "switch".
+    pub kind: &'static str,
+    pub notes: Option<&'a str>,
+    pub location: Option<&'a str>,
+    pub state: Option<&'a DeviceSnapshot>,
+}
+
+impl<'a> DeviceContext<'a> {
+    pub fn new(
+        device: &'a Device,
+        setting: Option<&'a DeviceSetting>,
+        state: Option<&'a DeviceSnapshot>,
+    ) -> Self {
+        Self {
+            name: &device.name,
+            id: &device.device,
+            model: &device.model,
+            kind: Kind::of(device).as_str(),
+            notes: setting.and_then(|s| s.notes.as_deref()),
+            location: setting.and_then(|s| s.location.as_deref()),
+            state,
+        }
+    }
+}
+
+/// A user supplied output template, e.g. `'{{name}}: {{state.power}}'`.
+pub struct Template<'source> {
+    env: Environment<'source>,
+}
+
+impl<'source> Template<'source> {
+    const NAME: &'static str = "output";
+
+    pub fn new(source: &'source str) -> Result<Self> {
+        let mut env = Environment::new();
+        env.add_template(Self::NAME, source)
+            .context("Invalid output template")?;
+
+        Ok(Self { env })
+    }
+
+    pub fn render<S: Serialize>(&self, ctx: S) -> Result<String> {
+        Ok(self.env.get_template(Self::NAME)?.render(ctx)?)
+    }
+}
@@ -0,0 +1,87 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::{sleep_until, Instant};
+
+/// The smallest spacing used once the api has pushed back at least once.
+const MIN_BACKOFF: Duration = Duration::from_millis(250);
+
+/// The largest spacing between requests, however hard the api pushes back.
+const MAX_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How much weight a new latency sample gets in the running average.
+const LATENCY_WEIGHT: f64 = 0.2;
+
+#[derive(Debug)]
+struct State {
+    interval: Duration,
+    next: Instant,
+    latency: Option<Duration>,
+}
+
+/// An adaptive limiter for requests to the cloud api.
+///
+/// Requests start unthrottled. Rate limit responses double the spacing
+/// between requests, unusually slow responses widen it a little, and each
+/// normal response narrows it again, so long-running commands settle just
+/// under whatever quota the api is currently enforcing.
+#[derive(Debug)]
+pub struct Throttle {
+    state: Mutex<State>,
+}
+
+impl Default for Throttle {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(State {
+                interval: Duration::ZERO,
+                next: Instant::now(),
+                latency: None,
+            }),
+        }
+    }
+}
+
+impl Throttle {
+    /// Wait for the next request slot.
+    pub async fn acquire(&self) {
+        let slot = {
+            let mut state = self.state.lock().expect("throttle lock poisoned");
+            let slot = state.next.max(Instant::now());
+            state.next = slot + state.interval;
+            slot
+        };
+
+        sleep_until(slot).await;
+    }
+
+    /// Record a successful request that took `latency`.
+    pub fn success(&self, latency: Duration) {
+        let mut state = self.state.lock().expect("throttle lock poisoned");
+
+        let average = match state.latency {
+            Some(average) => {
+                average.mul_f64(1.0 - LATENCY_WEIGHT) + latency.mul_f64(LATENCY_WEIGHT)
+            }
+            None => latency,
+        };
+        state.latency = Some(average);
+
+        if latency > average * 2 {
+            state.interval = (state.interval + MIN_BACKOFF).min(MAX_INTERVAL);
+        } else {
+            state.interval = state.interval.mul_f64(0.9);
+        }
+    }
+
+    /// Record a rate limit response, returning how long to wait before
+    /// retrying.
+    pub fn rate_limited(&self) -> Duration {
+        let mut state = self.state.lock().expect("throttle lock poisoned");
+
+        state.interval = (state.interval * 2).clamp(MIN_BACKOFF, MAX_INTERVAL);
+        state.next = Instant::now() + state.interval;
+
+        state.interval
+    }
+}
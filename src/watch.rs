@@ -0,0 +1,236 @@
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use chrono::Local;
+use govee_rs::models::{Device, Devices, PowerState};
+use tokio::time::Instant;
+
+use crate::{
+    auto_off::Rule,
+    cache::{self, StateCache},
+    capability,
+    client::Client,
+    events,
+    history::History,
+    notify::{Event, Notification, Notifiers},
+    settings::Settings,
+    state::DeviceSnapshot,
+};
+
+/// Poll `devices` every `interval`, running configured hooks when a device
+/// changes power state and notifying when one goes offline or comes back.
+/// Runs until interrupted.
+///
+/// Devices covered by an `auto_off` rule are turned off or dimmed once
+/// they've been seen on for longer than the rule allows. Time on before
+/// watching started isn't known, so it counts from the first poll.
+///
+/// Every poll also refreshes the state cache, so while this runs commands
+/// that read the cache (like statusbar) don't need their own api calls, and
+/// is sampled into the history database for `spirit history`.
+///
+/// The first poll only records a baseline, so hooks fire for changes made
+/// while watching rather than for whatever state the devices start in.
+///
+/// `on_change` is run for every transition, power or connectivity, with
+/// `{device}`, `{old}` and `{new}` replaced.
+pub async fn watch(
+    client: &Client,
+    settings: &Settings,
+    devices: &Devices,
+    interval: Duration,
+    on_change: Option<&str>,
+) -> Result<()> {
+    let device_settings = settings.device_settings();
+    let notifiers = Notifiers::from_settings(settings)?;
+    let mut last: HashMap<String, DeviceSnapshot> = HashMap::new();
+    let mut cache = StateCache::load()?;
+    let history = match History::open(cache::now()) {
+        Ok(history) => Some(history),
+        Err(e) => {
+            events::record("error", format!("watch: {:#}", e));
+            None
+        }
+    };
+
+    let mut rules = Vec::new();
+    for setting in settings.auto_off.iter().flatten() {
+        rules.push(Rule::from_setting(setting).context("Invalid auto_off rule")?);
+    }
+    let mut on_since: HashMap<String, Instant> = HashMap::new();
+    let mut handled: HashSet<String> = HashSet::new();
+
+    loop {
+        for device in devices.iter() {
+            let current = match client.refresh(device).await {
+                Ok(current) => current,
+                Err(e) => {
+                    events::record("error", format!("watch {}: {:#}", device.name, e));
+                    continue;
+                }
+            };
+
+            if let Some(previous) = last.get(&device.name) {
+                if previous.online != current.online {
+                    let (event, state) = match current.online {
+                        Some(false) => (Event::DeviceOffline, "offline"),
+                        _ => (Event::DeviceOnline, "online"),
+                    };
+
+                    events::record("change", format!("{} went {}", device.name, state));
+
+                    if let Some(template) = on_change {
+                        let old = match previous.online {
+                            Some(false) => "offline",
+                            Some(true) => "online",
+                            None => "unknown",
+                        };
+                        run_on_change(template, &device.name, old, state);
+                    }
+                    notifiers
+                        .notify(Notification::new(
+                            event,
+                            format!("{} is {}", device.name, state),
+                            format!("{} ({}) went {}", device.name, device.model, state),
+                        ))
+                        .await;
+                }
+
+                if previous.power != current.power {
+                    let setting = device_settings.get(&device.name);
+                    let (state, hook) = match current.power {
+                        Some(true) => ("on", setting.and_then(|s| s.on_power_on.as_deref())),
+                        _ => ("off", setting.and_then(|s| s.on_power_off.as_deref())),
+                    };
+
+                    events::record("change", format!("{} turned {}", device.name, state));
+
+                    if let Some(template) = on_change {
+                        let old = match previous.power {
+                            Some(true) => "on",
+                            Some(false) => "off",
+                            None => "unknown",
+                        };
+                        run_on_change(template, &device.name, old, state);
+                    }
+
+                    if let Some(hook) = hook {
+                        run_hook(hook, &device.name, state);
+                    }
+                }
+            }
+
+            if current.power == Some(true) {
+                let since = *on_since
+                    .entry(device.name.clone())
+                    .or_insert_with(Instant::now);
+
+                if !handled.contains(&device.name) {
+                    let setting = device_settings.get(&device.name);
+                    let due = rules
+                        .iter()
+                        .filter(|r| r.applies_to(device, setting))
+                        .filter(|r| r.in_window(Local::now().time()))
+                        .find(|r| since.elapsed() >= r.after);
+
+                    if let Some(rule) = due {
+                        auto_off(client, device, rule, since.elapsed()).await;
+                        handled.insert(device.name.clone());
+                    }
+                }
+            } else {
+                on_since.remove(&device.name);
+                handled.remove(&device.name);
+            }
+
+            let now = cache::now();
+            if let Some(ref history) = history {
+                if let Err(e) = history.record(&device.name, &current, now, interval) {
+                    events::record("error", format!("watch: {:#}", e));
+                }
+            }
+
+            cache.record(&device.name, current.clone(), now);
+            last.insert(device.name.clone(), current);
+        }
+
+        if let Err(e) = cache.save() {
+            events::record("error", format!("watch: {:#}", e));
+        }
+
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Start `hook` with `sh -c` without waiting for it.
+///
+/// The device name and new state are passed as SPIRIT_DEVICE and
+/// SPIRIT_STATE.
+fn run_hook(hook: &str, device: &str, state: &str) {
+    let res = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .env("SPIRIT_DEVICE", device)
+        .env("SPIRIT_STATE", state)
+        .spawn();
+
+    match res {
+        Ok(_) => events::record("hook", format!("{}: {}", device, hook)),
+        Err(e) => events::record("error", format!("hook for {} failed: {}", device, e)),
+    }
+}
+
+/// Apply an auto-off rule to `device`, which has been on for `on_for`.
+///
+/// Dimming only applies to lights; switches are turned off.
+async fn auto_off(client: &Client, device: &Device, rule: &Rule, on_for: Duration) {
+    let on_for = humantime::format_duration(Duration::from_secs(on_for.as_secs()));
+
+    let (res, done) = match rule.dim {
+        Some(level) if capability::is_light(device) => (
+            client.brightness(device, level).await,
+            format!("dimmed to {}%", level),
+        ),
+        _ => (
+            client.turn(device, PowerState::Off).await,
+            "turned off".to_string(),
+        ),
+    };
+
+    match res {
+        Ok(()) => events::record(
+            "auto-off",
+            format!("{} {} after being on for {}", device.name, done, on_for),
+        ),
+        Err(e) => events::record("error", format!("auto-off {}: {:#}", device.name, e)),
+    }
+}
+
+/// Fill in and start an `--on-change` command without waiting for it.
+///
+/// The device name is shell quoted since it comes from the api. The values
+/// are also passed as SPIRIT_DEVICE, SPIRIT_OLD and SPIRIT_NEW.
+fn run_on_change(template: &str, device: &str, old: &str, new: &str) {
+    let command = template
+        .replace("{device}", &shell_quote(device))
+        .replace("{old}", old)
+        .replace("{new}", new);
+
+    let res = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .env("SPIRIT_DEVICE", device)
+        .env("SPIRIT_OLD", old)
+        .env("SPIRIT_NEW", new)
+        .spawn();
+
+    match res {
+        Ok(_) => events::record("hook", format!("{}: {}", device, command)),
+        Err(e) => events::record("error", format!("on-change for {} failed: {}", device, e)),
+    }
+}
+
+fn shell_quote(raw: &str) -> String {
+    format!("'{}'", raw.replace('\'', "'\\''"))
+}
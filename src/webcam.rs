@@ -0,0 +1,40 @@
+#[cfg(target_os = "linux")]
+use std::fs;
+
+/// Whether any process currently has a video capture device open.
+///
+/// This walks the fd tables in /proc looking for links to /dev/video*, so
+/// it only sees processes the current user is allowed to inspect.
+#[cfg(target_os = "linux")]
+pub fn in_use() -> bool {
+    let procs = match fs::read_dir("/proc") {
+        Ok(procs) => procs,
+        Err(_) => return false,
+    };
+
+    procs
+        .flatten()
+        .filter(|p| {
+            p.file_name()
+                .to_str()
+                .map(|n| n.chars().all(|c| c.is_ascii_digit()))
+                .unwrap_or(false)
+        })
+        .any(|p| {
+            let fds = match fs::read_dir(p.path().join("fd")) {
+                Ok(fds) => fds,
+                Err(_) => return false,
+            };
+
+            fds.flatten().any(|fd| {
+                fs::read_link(fd.path())
+                    .map(|target| target.to_string_lossy().starts_with("/dev/video"))
+                    .unwrap_or(false)
+            })
+        })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn in_use() -> bool {
+    false
+}
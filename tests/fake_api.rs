@@ -0,0 +1,105 @@
+//! Full cli flows against the spirit-fake-api server.
+//!
+//! Run with `cargo test --features fake-api`.
+#![cfg(feature = "fake-api")]
+
+use std::{
+    env, fs,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    process::{Child, Command, Output, Stdio},
+};
+
+struct FakeApi {
+    child: Child,
+    url: String,
+}
+
+impl FakeApi {
+    fn start(args: &[&str]) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_spirit-fake-api"))
+            .args(["--addr", "127.0.0.1:0"])
+            .args(args)
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("could not start spirit-fake-api");
+
+        let mut url = String::new();
+        BufReader::new(child.stdout.as_mut().unwrap())
+            .read_line(&mut url)
+            .expect("could not read fake api address");
+
+        Self {
+            child,
+            url: url.trim().to_string(),
+        }
+    }
+}
+
+impl Drop for FakeApi {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A scratch home and working directory holding `config` as spirit.toml.
+fn workspace(name: &str, config: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("spirit-test-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("spirit.toml"), config).unwrap();
+    dir
+}
+
+fn spirit(api: &FakeApi, dir: &PathBuf, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_spirit"))
+        .current_dir(dir)
+        .env("HOME", dir)
+        .env_remove("XDG_DATA_HOME")
+        .env_remove("XDG_CACHE_HOME")
+        .args(["--govee-key", "fake", "--api-url", &api.url])
+        .args(args)
+        .output()
+        .expect("could not run spirit")
+}
+
+#[test]
+fn list_shows_fake_devices() {
+    let api = FakeApi::start(&["--devices", "2"]);
+    let dir = workspace("list", "");
+
+    let out = spirit(&api, &dir, &["--all", "list", "--template", "{{name}}"]);
+    assert!(out.status.success(), "{:?}", out);
+
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert_eq!(
+        stdout.lines().collect::<Vec<_>>(),
+        ["Fake Light 1", "Fake Light 2"]
+    );
+}
+
+#[test]
+fn check_sets_fail_color_and_keeps_exit_code() {
+    let api = FakeApi::start(&["--devices", "1"]);
+    let dir = workspace("check", "success = \"#00ff00\"\nfail = \"#ff0000\"\n");
+
+    let out = spirit(&api, &dir, &["--all", "check", "--", "sh", "-c", "exit 3"]);
+    assert_eq!(out.status.code(), Some(3), "{:?}", out);
+
+    let out = spirit(
+        &api,
+        &dir,
+        &[
+            "--all",
+            "info",
+            "--template",
+            "{{state.power}} {{state.color}}",
+        ],
+    );
+    assert!(out.status.success(), "{:?}", out);
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap().trim(),
+        "true #ff0000"
+    );
+}